@@ -0,0 +1,72 @@
+// Measures flag reads from an environment held locally while 64 readers evaluate
+// concurrently, through both the async and the synchronous read paths.
+// Run with `cargo bench --bench concurrent_reads`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use flagsmith::flagsmith::offline_handler::LocalFileHandler;
+use flagsmith::{Flagsmith, FlagsmithOptions};
+use std::sync::Arc;
+
+const READERS: usize = 64;
+const READS_PER_READER: usize = 100;
+
+fn offline_flagsmith(runtime: &tokio::runtime::Runtime) -> Arc<Flagsmith> {
+    let handler = LocalFileHandler::new("tests/fixtures/environment.json").unwrap();
+    let options = FlagsmithOptions {
+        offline_handler: Some(Box::new(handler)),
+        offline_mode: true,
+        ..Default::default()
+    };
+    Arc::new(runtime.block_on(Flagsmith::new("ser.environment_key".to_string(), options)))
+}
+
+fn concurrent_reads(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let flagsmith = offline_flagsmith(&runtime);
+    let mut group = c.benchmark_group("concurrent_reads");
+    group.throughput(Throughput::Elements((READERS * READS_PER_READER) as u64));
+
+    group.bench_function(BenchmarkId::new("async", READERS), |b| {
+        b.to_async(&runtime).iter(|| async {
+            let readers: Vec<_> = (0..READERS)
+                .map(|reader| {
+                    let flagsmith = Arc::clone(&flagsmith);
+                    tokio::spawn(async move {
+                        let identifier = format!("identity-{}", reader);
+                        for _ in 0..READS_PER_READER {
+                            flagsmith
+                                .get_identity_flags(identifier.as_str())
+                                .await
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for reader in readers {
+                reader.await.unwrap();
+            }
+        })
+    });
+
+    group.bench_function(BenchmarkId::new("sync", READERS), |b| {
+        b.iter(|| {
+            std::thread::scope(|scope| {
+                for reader in 0..READERS {
+                    let local = flagsmith.local();
+                    scope.spawn(move || {
+                        let identifier = format!("identity-{}", reader);
+                        for _ in 0..READS_PER_READER {
+                            local.identity_flags(identifier.as_str()).unwrap();
+                        }
+                    });
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_reads);
+criterion_main!(benches);
@@ -0,0 +1,148 @@
+// Derives `flagsmith::FromFlags` for structs whose fields are read from `Flags`.
+//
+// Each field is looked up by its name, or by `#[flagsmith(name = "...")]`.
+// `bool` fields read whether the feature is enabled, unless marked with
+// `#[flagsmith(value)]`; all other fields deserialize the feature value with
+// `Flag::value_as`. `#[flagsmith(default = <expr>)]` is used when the feature
+// does not exist. Every missing or mistyped feature is reported at once.
+// # Example
+// ```
+// use flagsmith::FlagsmithFlags;
+// #[derive(FlagsmithFlags)]
+// struct Features {
+//     new_checkout: bool,
+//     #[flagsmith(name = "max_cart_items", default = 10)]
+//     max_items: u32,
+// }
+// let features: Features = flags.typed()?;
+// ```
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, LitStr, Type};
+
+#[proc_macro_derive(FlagsmithFlags, attributes(flagsmith))]
+pub fn derive_flagsmith_flags(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+struct FieldOptions {
+    name: Option<String>,
+    default: Option<Expr>,
+    value: bool,
+}
+
+fn field_options(field: &syn::Field) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions {
+        name: None,
+        default: None,
+        value: false,
+    };
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("flagsmith"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                options.name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("default") {
+                options.default = Some(meta.value()?.parse::<Expr>()?);
+            } else if meta.path.is_ident("value") {
+                options.value = true;
+            } else {
+                return Err(meta.error("expected `name`, `default` or `value`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+fn is_bool(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.qself.is_none() && path.path.is_ident("bool"))
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "FlagsmithFlags can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "FlagsmithFlags can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut lookups = vec![];
+    let mut initializers = vec![];
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let options = field_options(field)?;
+        let feature_name = options.name.unwrap_or_else(|| ident.unraw().to_string());
+        let local = format_ident!("__field_{}", ident.unraw());
+        let read = if is_bool(ty) && !options.value {
+            quote!(::std::result::Result::<#ty, ::flagsmith::error::Error>::Ok(flag.enabled))
+        } else {
+            quote!(flag.value_as::<#ty>())
+        };
+        let has_default = options.default.is_some();
+        let missing = match options.default {
+            Some(default) => quote!(::std::option::Option::Some(#default)),
+            None => quote! {{
+                __errors.push(::flagsmith::error::Error::FlagNotFound {
+                    name: #feature_name.to_string(),
+                });
+                ::std::option::Option::None
+            }},
+        };
+        lookups.push(quote! {
+            let #local: ::std::option::Option<#ty> =
+                match ::flagsmith::__private::lookup(flags, #feature_name, #has_default) {
+                    ::std::option::Option::Some(flag) => match #read {
+                        ::std::result::Result::Ok(value) => ::std::option::Option::Some(value),
+                        ::std::result::Result::Err(e) => {
+                            __errors.push(e);
+                            ::std::option::Option::None
+                        }
+                    },
+                    ::std::option::Option::None => #missing,
+                };
+        });
+        initializers.push(quote!(#ident: #local.unwrap()));
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::flagsmith::FromFlags for #name #ty_generics #where_clause {
+            fn from_flags(
+                flags: &::flagsmith::flagsmith::models::Flags,
+            ) -> ::std::result::Result<Self, ::flagsmith::error::Error> {
+                let mut __errors = ::std::vec::Vec::new();
+                #(#lookups)*
+                if !__errors.is_empty() {
+                    return ::std::result::Result::Err(::flagsmith::error::Error::InvalidFlags {
+                        errors: __errors,
+                    });
+                }
+                ::std::result::Result::Ok(Self {
+                    #(#initializers,)*
+                })
+            }
+        }
+    })
+}
@@ -0,0 +1,98 @@
+// A blocking client for applications that do not run a Tokio runtime, e.g. CLI
+// tools and batch jobs. It wraps the async `Flagsmith` client and runs it, along
+// with its background tasks (polling, analytics), on a runtime thread it owns.
+//
+// Its methods must not be called from within an async runtime, as they block
+// the calling thread until the request completes.
+// # Example
+// ```
+// use flagsmith::blocking::Flagsmith;
+// use flagsmith::FlagsmithOptions;
+// fn main() {
+//     let flagsmith = Flagsmith::new(
+//         "YOUR_ENVIRONMENT_KEY".to_string(),
+//         FlagsmithOptions::default(),
+//     );
+//     let flags = flagsmith.get_environment_flags().unwrap();
+// }
+// ```
+use crate::error;
+use crate::flagsmith::context::IdentityContext;
+use crate::flagsmith::models::Flags;
+use crate::FlagsmithOptions;
+use flagsmith_flag_engine::segments::Segment;
+
+pub struct Flagsmith {
+    inner: crate::Flagsmith,
+    runtime: tokio::runtime::Runtime,
+}
+
+// Shuts the client down before its runtime, which would otherwise cancel the
+// background tasks and lose the final analytics flush
+impl Drop for Flagsmith {
+    fn drop(&mut self) {
+        let _ = self.runtime.block_on(self.inner.shutdown());
+    }
+}
+
+impl Flagsmith {
+    // Panics if the options are invalid or, with local evaluation enabled, if the
+    // initial environment fetch fails. Use `Flagsmith::try_new` to handle these
+    // cases as errors instead.
+    pub fn new(environment_key: String, flagsmith_options: FlagsmithOptions) -> Self {
+        match Flagsmith::try_new(environment_key, flagsmith_options) {
+            Ok(flagsmith) => flagsmith,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(
+        environment_key: String,
+        flagsmith_options: FlagsmithOptions,
+    ) -> Result<Self, error::Error> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("flagsmith-blocking")
+            .enable_all()
+            .build()
+            .map_err(|e| error::Error::Client(format!("unable to start runtime: {}", e)))?;
+        let inner = runtime.block_on(crate::Flagsmith::try_new(
+            environment_key,
+            flagsmith_options,
+        ))?;
+        Ok(Flagsmith { inner, runtime })
+    }
+
+    // See `flagsmith::Flagsmith::get_environment_flags`
+    pub fn get_environment_flags(&self) -> Result<Flags, error::Error> {
+        self.runtime.block_on(self.inner.get_environment_flags())
+    }
+
+    // See `flagsmith::Flagsmith::get_identity_flags`
+    pub fn get_identity_flags(
+        &self,
+        context: impl Into<IdentityContext>,
+    ) -> Result<Flags, error::Error> {
+        self.runtime
+            .block_on(self.inner.get_identity_flags(context))
+    }
+
+    // See `flagsmith::Flagsmith::get_identity_segments`
+    pub fn get_identity_segments(
+        &self,
+        context: impl Into<IdentityContext>,
+    ) -> Result<Vec<Segment>, error::Error> {
+        self.runtime
+            .block_on(self.inner.get_identity_segments(context))
+    }
+
+    // See `flagsmith::Flagsmith::update_environment`
+    pub fn update_environment(&mut self) -> Result<(), error::Error> {
+        self.runtime.block_on(self.inner.update_environment())
+    }
+
+    // See `flagsmith::Flagsmith::shutdown`
+    pub fn shutdown(&self) -> Result<(), error::Error> {
+        self.runtime.block_on(self.inner.shutdown())
+    }
+}
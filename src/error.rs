@@ -1,50 +1,327 @@
 use std::convert::From;
 use std::fmt;
 
-/// Wraps several types of errors.
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors returned by the Flagsmith client.
 #[derive(Debug)]
-pub struct Error {
-    pub kind: ErrorKind,
-    pub msg: String,
+#[non_exhaustive]
+pub enum Error {
+    /// The API responded with a non-success status code.
+    Http {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    /// The request to the API timed out.
+    Timeout(reqwest::Error),
+    /// The request could not be sent or its response could not be read.
+    Transport(reqwest::Error),
+    /// A response or environment document could not be decoded.
+    Decode {
+        msg: String,
+        source: Option<BoxError>,
+    },
+    /// The requested flag does not exist and no default handler is configured.
+    FlagNotFound { name: String },
+    /// The value of a flag could not be converted to the requested type.
+    InvalidFlagValue {
+        name: String,
+        source: serde_json::Error,
+    },
+    /// Some features of a `FromFlags` type are missing or have invalid values.
+    InvalidFlags { errors: Vec<Error> },
+    /// The client options are invalid.
+    Configuration(ConfigurationError),
+    /// The operation needs the environment to be evaluated locally.
+    LocalEvaluationRequired,
+    /// The environment store holds no environment yet, e.g. because the leader has
+    /// not published one.
+    EmptyEnvironmentStore,
+    /// Any other failure inside the client.
+    Client(String),
 }
 
-/// Defines error kind.
+/// Defines the coarse category of an `Error`.
 #[derive(Debug, PartialEq)]
 pub enum ErrorKind {
     FlagsmithClientError,
     FlagsmithAPIError,
+    FlagsmithConfigurationError(ConfigurationError),
+}
+
+/// Describes an invalid combination of `FlagsmithOptions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigurationError {
+    OfflineModeWithoutOfflineHandler,
+    OfflineFallbackWithoutOfflineHandler,
+    OfflineFallbackWithOfflineMode,
+    MultipleOfflineHandlers,
+    DefaultHandlerWithOfflineHandler,
+    LocalEvaluationWithOfflineHandler,
+    RealtimeWithoutLocalEvaluation,
+    EnvironmentStoreWithoutLocalEvaluation,
+    FollowerWithoutEnvironmentStore,
+    RealtimeWithFollower,
+    InvalidEnvironmentKey,
+}
+
+impl fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigurationError::OfflineModeWithoutOfflineHandler => {
+                write!(f, "offline_handler must be set to use offline_mode")
+            }
+            ConfigurationError::OfflineFallbackWithoutOfflineHandler => {
+                write!(f, "offline_handler must be set to use offline_fallback")
+            }
+            ConfigurationError::OfflineFallbackWithOfflineMode => {
+                write!(f, "offline_fallback cannot be used with offline_mode")
+            }
+            ConfigurationError::MultipleOfflineHandlers => {
+                write!(
+                    f,
+                    "offline_handler and async_offline_handler cannot be used together"
+                )
+            }
+            ConfigurationError::DefaultHandlerWithOfflineHandler => {
+                write!(
+                    f,
+                    "default_flag_handler cannot be used with offline_handler unless offline_fallback is set"
+                )
+            }
+            ConfigurationError::LocalEvaluationWithOfflineHandler => {
+                write!(
+                    f,
+                    "offline_handler cannot be used with local evaluation unless offline_fallback is set"
+                )
+            }
+            ConfigurationError::RealtimeWithoutLocalEvaluation => {
+                write!(
+                    f,
+                    "enable_realtime_updates requires enable_local_evaluation"
+                )
+            }
+            ConfigurationError::EnvironmentStoreWithoutLocalEvaluation => {
+                write!(f, "environment_store requires enable_local_evaluation")
+            }
+            ConfigurationError::FollowerWithoutEnvironmentStore => {
+                write!(
+                    f,
+                    "environment_store must be set to use environment_store_follower"
+                )
+            }
+            ConfigurationError::RealtimeWithFollower => {
+                write!(
+                    f,
+                    "enable_realtime_updates cannot be used with environment_store_follower"
+                )
+            }
+            ConfigurationError::InvalidEnvironmentKey => {
+                write!(f, "environment key is not a valid header value")
+            }
+        }
+    }
 }
-impl Error{
-    pub fn new(kind: ErrorKind, msg: String) -> Error{
-        Error{
-            kind,
-            msg
+
+impl std::error::Error for ConfigurationError {}
+
+impl Error {
+    /// Creates a `Decode` error without an underlying cause.
+    pub fn decode(msg: &str) -> Error {
+        Error::Decode {
+            msg: msg.to_string(),
+            source: None,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Http { .. } | Error::Timeout(_) | Error::Transport(_) | Error::Decode { .. } => {
+                ErrorKind::FlagsmithAPIError
+            }
+            Error::FlagNotFound { .. }
+            | Error::InvalidFlagValue { .. }
+            | Error::InvalidFlags { .. }
+            | Error::LocalEvaluationRequired
+            | Error::EmptyEnvironmentStore
+            | Error::Client(_) => ErrorKind::FlagsmithClientError,
+            Error::Configuration(e) => ErrorKind::FlagsmithConfigurationError(e.clone()),
+        }
+    }
+
+    /// Returns true if the same request may succeed when retried later, i.e. the
+    /// failure was a timeout, a network error, a server error, rate limiting or an
+    /// environment store that was not written yet.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Http { status, .. } => {
+                status.is_server_error()
+                    || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    || *status == reqwest::StatusCode::REQUEST_TIMEOUT
+            }
+            Error::Timeout(_) | Error::Transport(_) | Error::EmptyEnvironmentStore => true,
+            _ => false,
+        }
+    }
+
+    /// Returns a copy of the error for callers sharing the result of a single
+    /// request. Errors holding a cause that cannot be copied (timeouts, transport
+    /// errors, invalid values) become `Client` errors with the same message.
+    pub(crate) fn duplicate(&self) -> Error {
+        match self {
+            Error::Http { status, body } => Error::Http {
+                status: *status,
+                body: body.clone(),
+            },
+            Error::Decode { msg, .. } => Error::decode(msg),
+            Error::FlagNotFound { name } => Error::FlagNotFound { name: name.clone() },
+            Error::InvalidFlags { errors } => Error::InvalidFlags {
+                errors: errors.iter().map(Error::duplicate).collect(),
+            },
+            Error::Configuration(e) => Error::Configuration(e.clone()),
+            Error::LocalEvaluationRequired => Error::LocalEvaluationRequired,
+            Error::EmptyEnvironmentStore => Error::EmptyEnvironmentStore,
+            Error::Client(msg) => Error::Client(msg.clone()),
+            e => Error::Client(e.to_string()),
         }
     }
 }
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.kind {
-            ErrorKind::FlagsmithClientError => write!(f, "Flagsmith API error: {}", &self.msg),
-            ErrorKind::FlagsmithAPIError => write!(f, "Flagsmith client error: {}", &self.msg),
+        match self {
+            Error::Http { status, body } if body.is_empty() => {
+                write!(f, "Flagsmith API error: API returned {}", status)
+            }
+            Error::Http { status, body } => {
+                write!(f, "Flagsmith API error: API returned {}: {}", status, body)
+            }
+            Error::Timeout(_) => write!(f, "Flagsmith API error: request timed out"),
+            Error::Transport(e) => write!(f, "Flagsmith API error: {}", e),
+            Error::Decode { msg, .. } => write!(f, "Flagsmith API error: {}", msg),
+            Error::FlagNotFound { name } => {
+                write!(f, "Flagsmith client error: flag {} not found", name)
+            }
+            Error::InvalidFlagValue { name, source } => write!(
+                f,
+                "Flagsmith client error: value of flag {} is invalid: {}",
+                name, source
+            ),
+            Error::InvalidFlags { errors } => {
+                write!(f, "Flagsmith client error: invalid flags: ")?;
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            }
+            Error::Configuration(e) => write!(f, "Flagsmith configuration error: {}", e),
+            Error::LocalEvaluationRequired => {
+                write!(f, "Flagsmith client error: local evaluation is required")
+            }
+            Error::EmptyEnvironmentStore => {
+                write!(f, "Flagsmith client error: the environment store is empty")
+            }
+            Error::Client(msg) => write!(f, "Flagsmith client error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Timeout(e) | Error::Transport(e) => Some(e),
+            Error::Decode {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            Error::InvalidFlagValue { source, .. } => Some(source),
+            Error::Configuration(e) => Some(e),
+            _ => None,
         }
     }
 }
 
+impl From<ConfigurationError> for Error {
+    fn from(e: ConfigurationError) -> Self {
+        Error::Configuration(e)
+    }
+}
+
 impl From<url::ParseError> for Error {
     fn from(e: url::ParseError) -> Self {
-        Error::new(ErrorKind::FlagsmithClientError, e.to_string())
+        Error::Client(e.to_string())
     }
 }
 
 impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Self {
-        Error::new(ErrorKind::FlagsmithAPIError, e.to_string())
+        if e.is_timeout() {
+            Error::Timeout(e)
+        } else if e.is_decode() {
+            Error::Decode {
+                msg: e.to_string(),
+                source: Some(Box::new(e)),
+            }
+        } else {
+            Error::Transport(e)
+        }
     }
 }
 
-impl  From<serde_json::Error> for Error {
+impl From<serde_json::Error> for Error {
     fn from(e: serde_json::Error) -> Self {
-        Error::new(ErrorKind::FlagsmithAPIError, e.to_string())
+        Error::Decode {
+            msg: e.to_string(),
+            source: Some(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn http_errors_are_retryable_only_for_transient_statuses() {
+        let http_error = |status| Error::Http {
+            status,
+            body: String::new(),
+        };
+        assert!(http_error(reqwest::StatusCode::SERVICE_UNAVAILABLE).is_retryable());
+        assert!(http_error(reqwest::StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!http_error(reqwest::StatusCode::UNAUTHORIZED).is_retryable());
+        assert!(!http_error(reqwest::StatusCode::NOT_FOUND).is_retryable());
+        assert!(!Error::FlagNotFound {
+            name: "flag".to_string()
+        }
+        .is_retryable());
+    }
+
+    #[test]
+    fn decode_errors_chain_their_source() {
+        let json_error = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let error: Error = json_error.into();
+        assert_eq!(error.kind(), ErrorKind::FlagsmithAPIError);
+        assert!(error.source().is_some());
+        assert!(error.to_string().starts_with("Flagsmith API error: "));
+    }
+
+    #[test]
+    fn configuration_errors_keep_their_message() {
+        let error: Error = ConfigurationError::OfflineModeWithoutOfflineHandler.into();
+        assert_eq!(
+            error.to_string(),
+            "Flagsmith configuration error: offline_handler must be set to use offline_mode"
+        );
+        assert_eq!(
+            error.kind(),
+            ErrorKind::FlagsmithConfigurationError(
+                ConfigurationError::OfflineModeWithoutOfflineHandler
+            )
+        );
     }
 }
@@ -1,17 +1,72 @@
+use super::backoff::Backoff;
+use crate::error;
 use flume;
+use futures_util::future::BoxFuture;
 use log::{debug, warn};
 use reqwest::header::HeaderMap;
 use serde_json;
 use std::collections::HashMap;
-use tokio::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
 use std::sync::Arc;
 static ANALYTICS_TIMER_IN_MILLI: u64 = 10 * 1000;
+// Evaluations waiting to be counted by the processor before new ones are dropped
+const ANALYTICS_CHANNEL_CAPACITY: usize = 10 * 1000;
+const ANALYTICS_RETRY_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const ANALYTICS_FINAL_FLUSH_ATTEMPTS: u32 = 3;
+const ANALYTICS_FINAL_FLUSH_BACKOFF: Duration = Duration::from_millis(200);
+
+// Resolves to the result of the final flush once the processor has stopped
+type ProcessorHandle = JoinHandle<Result<(), error::Error>>;
+// A flush in progress, resolving to the counts it sent along with its result
+type PendingFlush<'a> = BoxFuture<'a, (HashMap<String, u32>, Result<(), error::Error>)>;
+
+// Decides what happens to an evaluation of a feature that is not pending yet
+// once the maximum number of pending features has been reached
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnalyticsOverflowPolicy {
+    // Keep the pending counts and drop the new evaluation
+    #[default]
+    DropNewest,
+    // Drop all pending counts to make room for the new evaluation
+    DropPending,
+}
+
+// Snapshot of the analytics delivery counters since the processor started
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AnalyticsStats {
+    // Evaluations discarded because the channel or the pending counts were full,
+    // or because the processor was already stopped
+    pub dropped_evaluations: u64,
+    // Flushes that failed; their counts are retried with the next flush
+    pub failed_flushes: u64,
+    pub successful_flushes: u64,
+}
+
+#[derive(Debug, Default)]
+struct AnalyticsCounters {
+    dropped_evaluations: AtomicU64,
+    failed_flushes: AtomicU64,
+    successful_flushes: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PendingLimit {
+    max_features: usize,
+    overflow_policy: AnalyticsOverflowPolicy,
+}
 
 #[derive(Clone, Debug)]
 pub struct AnalyticsProcessor {
     pub tx: flume::Sender<String>,
     _analytics_data: Arc<RwLock<HashMap<String, u32>>>,
+    counters: Arc<AnalyticsCounters>,
+    shutdown_tx: Arc<watch::Sender<()>>,
+    handle: Arc<std::sync::Mutex<Option<ProcessorHandle>>>,
 }
 
 impl AnalyticsProcessor {
@@ -20,102 +75,265 @@ impl AnalyticsProcessor {
         headers: HeaderMap,
         timeout: std::time::Duration,
         timer: Option<u64>,
+        max_pending_features: usize,
+        overflow_policy: AnalyticsOverflowPolicy,
     ) -> Self {
-        let (tx, rx) = flume::unbounded();
+        let (tx, rx) = flume::bounded(ANALYTICS_CHANNEL_CAPACITY);
         let client = reqwest::Client::builder()
             .default_headers(headers)
             .timeout(timeout)
             .build()
             .unwrap();
         let analytics_endpoint = format!("{}analytics/flags/", api_url);
-        let timer = timer.unwrap_or(ANALYTICS_TIMER_IN_MILLI);
+        let timer = Duration::from_millis(timer.unwrap_or(ANALYTICS_TIMER_IN_MILLI));
+        let limit = PendingLimit {
+            max_features: max_pending_features,
+            overflow_policy,
+        };
+        // Failed flushes are retried sooner than the regular timer, backing off
+        // up to it during a longer outage
+        let backoff = Backoff::new(timer.min(ANALYTICS_RETRY_INITIAL_BACKOFF), timer);
 
         let analytics_data_arc: Arc<RwLock<HashMap<String, u32>>> =
             Arc::new(RwLock::new(HashMap::new()));
+        let counters = Arc::new(AnalyticsCounters::default());
 
         let analytics_data_locked = Arc::clone(&analytics_data_arc);
-        tokio::spawn(async move {
-            let mut last_flushed = chrono::Utc::now();
+        let task_counters = Arc::clone(&counters);
+        let (shutdown_tx, mut shutdown_rx) = watch::channel(());
+        let handle = tokio::spawn(async move {
+            let counters = task_counters;
+            let flush_timer = tokio::time::sleep(timer);
+            tokio::pin!(flush_timer);
+            let mut failures = 0;
+            // Evaluations keep being counted while a flush is in flight, so that the
+            // channel does not fill up while the API is slow or unavailable
+            let mut pending_flush: Option<PendingFlush> = None;
             loop {
-                let data = rx.try_recv();
-                let mut analytics_data = analytics_data_locked.write().await;
-                match data {
-                    // Update the analytics data with feature_id received
-                    Ok(feature_name) => {
-                        analytics_data
-                            .entry(feature_name)
-                            .and_modify(|e| *e += 1)
-                            .or_insert(1);
+                tokio::select! {
+                    _ = shutdown_rx.changed() => break,
+                    feature_name = rx.recv_async() => match feature_name {
+                        // Count everything that is already queued under a single lock
+                        Ok(feature_name) => {
+                            let mut analytics_data = analytics_data_locked.write().await;
+                            for feature_name in std::iter::once(feature_name).chain(rx.drain()) {
+                                add_count(&mut analytics_data, feature_name, 1, limit, &counters);
+                            }
+                        }
+                        Err(flume::RecvError::Disconnected) => break,
+                    },
+                    _ = &mut flush_timer, if pending_flush.is_none() => {
+                        let analytics_data = std::mem::take(&mut *analytics_data_locked.write().await);
+                        let (client, analytics_endpoint, counters) =
+                            (&client, &analytics_endpoint, &counters);
+                        pending_flush = Some(Box::pin(async move {
+                            let result =
+                                flush(client, &analytics_data, analytics_endpoint, counters).await;
+                            (analytics_data, result)
+                        }));
                     }
-                    Err(flume::TryRecvError::Empty) => {}
-                    Err(flume::TryRecvError::Disconnected) => {
-                        debug!("Shutting down analytics thread ");
-                        break;
+                    (analytics_data, result) = async { pending_flush.as_mut().unwrap().await },
+                        if pending_flush.is_some() =>
+                    {
+                        pending_flush = None;
+                        let mut delay = timer;
+                        match result {
+                            Ok(_) => failures = 0,
+                            Err(e) if e.is_retryable() => {
+                                failures += 1;
+                                delay = backoff.delay(failures);
+                                warn!(
+                                    "Failed to send analytics data, retrying in {:?}: {}",
+                                    delay, e
+                                );
+                                merge_counts(&analytics_data_locked, analytics_data, limit, &counters)
+                                    .await;
+                            }
+                            // Retrying a request the API rejected would fail the same way
+                            Err(e) => {
+                                failures = 0;
+                                warn!("Dropping analytics data rejected by the API: {}", e);
+                                let dropped: u32 = analytics_data.values().sum();
+                                counters
+                                    .dropped_evaluations
+                                    .fetch_add(dropped.into(), Ordering::Relaxed);
+                            }
+                        }
+                        flush_timer.as_mut().reset(Instant::now() + delay);
                     }
-                };
-                if (chrono::Utc::now() - last_flushed).num_milliseconds() > timer as i64 {
-                    flush(&client, &analytics_data, &analytics_endpoint).await;
-                    analytics_data.clear();
-                    last_flushed = chrono::Utc::now();
                 }
             }
+            // Let a flush in flight complete so that its counts are neither lost nor sent twice
+            if let Some(pending_flush) = pending_flush.take() {
+                let (analytics_data, result) = pending_flush.await;
+                if result.is_err_and(|e| e.is_retryable()) {
+                    merge_counts(&analytics_data_locked, analytics_data, limit, &counters).await;
+                }
+            }
+            debug!("Shutting down analytics thread ");
+            // Count whatever is still queued and flush it one last time
+            let mut analytics_data = std::mem::take(&mut *analytics_data_locked.write().await);
+            for feature_name in rx.drain() {
+                add_count(&mut analytics_data, feature_name, 1, limit, &counters);
+            }
+            final_flush(
+                &client,
+                &analytics_data,
+                &analytics_endpoint,
+                &counters,
+                timeout,
+            )
+            .await
         });
-        // thread::Builder::new()
-        //     .name("Analytics Processor".to_string())
-        //     .spawn(move || {
-        //         let mut last_flushed = chrono::Utc::now();
-        //         loop {
-        //             let data = rx.try_recv();
-        //             let mut analytics_data = analytics_data_locked.write().unwrap();
-        //             match data {
-        //                 // Update the analytics data with feature_id received
-        //                 Ok(feature_name) => {
-        //                     analytics_data
-        //                         .entry(feature_name)
-        //                         .and_modify(|e| *e += 1)
-        //                         .or_insert(1);
-        //                 }
-        //                 Err(flume::TryRecvError::Empty) => {}
-        //                 Err(flume::TryRecvError::Disconnected) => {
-        //                     debug!("Shutting down analytics thread ");
-        //                     break;
-        //                 }
-        //             };
-        //             if (chrono::Utc::now() - last_flushed).num_milliseconds() > timer as i64 {
-        //                 flush(&client, &analytics_data, &analytics_endpoint).await;
-        //                 analytics_data.clear();
-        //                 last_flushed = chrono::Utc::now();
-        //             }
-        //         }
-        //     })
-        //     .expect("Failed to start analytics thread");
-
-        return AnalyticsProcessor {
+
+        AnalyticsProcessor {
             tx,
             _analytics_data: Arc::clone(&analytics_data_arc),
-        };
+            counters,
+            shutdown_tx: Arc::new(shutdown_tx),
+            handle: Arc::new(std::sync::Mutex::new(Some(handle))),
+        }
     }
+
+    // Queues an evaluation of the given feature, dropping it if the processor
+    // has fallen too far behind or was stopped
     pub fn track_feature(&self, feature_name: &str) {
-        self.tx.send(feature_name.to_string()).unwrap();
+        if self.tx.try_send(feature_name.to_string()).is_err() {
+            self.counters
+                .dropped_evaluations
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> AnalyticsStats {
+        AnalyticsStats {
+            dropped_evaluations: self.counters.dropped_evaluations.load(Ordering::Relaxed),
+            failed_flushes: self.counters.failed_flushes.load(Ordering::Relaxed),
+            successful_flushes: self.counters.successful_flushes.load(Ordering::Relaxed),
+        }
+    }
+
+    // Signals the processor to perform a final flush and stop, without waiting for it
+    pub fn close(&self) {
+        let _ = self.shutdown_tx.send(());
+    }
+
+    // Stops the processor and returns the result of its final flush. Only the
+    // first call waits for the flush; subsequent calls return immediately.
+    pub async fn shutdown(&self) -> Result<(), error::Error> {
+        self.close();
+        let handle = self.handle.lock().unwrap().take();
+        match handle {
+            Some(handle) => handle
+                .await
+                .map_err(|e| error::Error::Client(e.to_string()))?,
+            None => Ok(()),
+        }
+    }
+}
+
+// Merges the counts of a failed flush back so that they are sent with the next attempt
+async fn merge_counts(
+    analytics_data: &RwLock<HashMap<String, u32>>,
+    counts: HashMap<String, u32>,
+    limit: PendingLimit,
+    counters: &AnalyticsCounters,
+) {
+    let mut pending = analytics_data.write().await;
+    for (feature_name, count) in counts {
+        add_count(&mut pending, feature_name, count, limit, counters);
     }
 }
 
+fn add_count(
+    analytics_data: &mut HashMap<String, u32>,
+    feature_name: String,
+    count: u32,
+    limit: PendingLimit,
+    counters: &AnalyticsCounters,
+) {
+    if !analytics_data.contains_key(&feature_name) && analytics_data.len() >= limit.max_features {
+        let dropped = match limit.overflow_policy {
+            AnalyticsOverflowPolicy::DropNewest => count,
+            AnalyticsOverflowPolicy::DropPending => {
+                let dropped = analytics_data.drain().map(|(_, count)| count).sum();
+                *analytics_data.entry(feature_name).or_insert(0) = count;
+                dropped
+            }
+        };
+        counters
+            .dropped_evaluations
+            .fetch_add(dropped.into(), Ordering::Relaxed);
+        return;
+    }
+    let pending = analytics_data.entry(feature_name).or_insert(0);
+    *pending = pending.saturating_add(count);
+}
+
 async fn flush(
     client: &reqwest::Client,
     analytics_data: &HashMap<String, u32>,
     analytics_endpoint: &str,
-) {
-    if analytics_data.len() == 0 {
-        return;
+    counters: &AnalyticsCounters,
+) -> Result<(), error::Error> {
+    if analytics_data.is_empty() {
+        return Ok(());
     }
     let body = serde_json::to_string(&analytics_data).unwrap();
-    let resp = client.post(analytics_endpoint).body(body).send().await;
-    if resp.is_err() {
-        warn!("Failed to send analytics data");
+    let result = match client.post(analytics_endpoint).body(body).send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(error::Error::Http {
+            status: response.status(),
+            body: response.text().await.unwrap_or_default(),
+        }),
+        Err(e) => Err(e.into()),
+    };
+    let counter = match result {
+        Ok(_) => &counters.successful_flushes,
+        Err(_) => &counters.failed_flushes,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    result
+}
+
+// Flushes the counts left on shutdown, which no later flush would carry. Transient
+// failures are retried a few times, as long as the whole flush takes less than
+// `timeout`.
+async fn final_flush(
+    client: &reqwest::Client,
+    analytics_data: &HashMap<String, u32>,
+    analytics_endpoint: &str,
+    counters: &AnalyticsCounters,
+    timeout: Duration,
+) -> Result<(), error::Error> {
+    let backoff = Backoff::new(
+        ANALYTICS_FINAL_FLUSH_BACKOFF,
+        ANALYTICS_FINAL_FLUSH_BACKOFF * 4,
+    );
+    let attempts = async {
+        let mut failures = 0;
+        loop {
+            match flush(client, analytics_data, analytics_endpoint, counters).await {
+                Err(e) if e.is_retryable() && failures + 1 < ANALYTICS_FINAL_FLUSH_ATTEMPTS => {
+                    failures += 1;
+                    warn!("Failed to send the final analytics data, retrying: {}", e);
+                    tokio::time::sleep(backoff.delay(failures)).await;
+                }
+                result => return result,
+            }
+        }
+    };
+    match tokio::time::timeout(timeout, attempts).await {
+        Ok(result) => result,
+        Err(_) => Err(error::Error::Client(format!(
+            "the final analytics flush did not complete within {:?}",
+            timeout
+        ))),
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use httpmock::prelude::*;
@@ -131,6 +349,8 @@ mod tests {
             header::HeaderMap::new(),
             std::time::Duration::from_secs(10),
             Some(10000),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
         )
         .await;
         // Now, let's make tracking calls
@@ -168,6 +388,8 @@ mod tests {
             headers,
             std::time::Duration::from_secs(10),
             Some(10),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
         )
         .await;
         // Now, let's update the analytics data
@@ -185,4 +407,313 @@ mod tests {
         let analytics_data = processor._analytics_data.read().await;
         assert_eq!(true, analytics_data.is_empty())
     }
+
+    #[tokio::test]
+    async fn shutdown_flushes_pending_analytics_data() {
+        // Given
+        let server = MockServer::start();
+        let flush_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/analytics/flags/")
+                .json_body(serde_json::json!({"feature_1": 2}));
+            then.status(200);
+        });
+        let processor = AnalyticsProcessor::new(
+            server.url("/api/v1/"),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            Some(10000),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+        processor.track_feature("feature_1");
+        processor.track_feature("feature_1");
+
+        // When
+        let result = processor.shutdown().await;
+
+        // Then
+        assert!(result.is_ok());
+        flush_mock.assert();
+        assert!(processor.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn shutdown_returns_error_if_final_flush_fails() {
+        // Given
+        let processor = AnalyticsProcessor::new(
+            "http://127.0.0.1:1/api/v1/".to_string(),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            Some(10000),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+        processor.track_feature("feature_1");
+
+        // When
+        let result = processor.shutdown().await;
+
+        // Then
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_retries_a_failed_final_flush() {
+        // Given
+        let server = MockServer::start();
+        let flush_mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/analytics/flags/");
+            then.status(503);
+        });
+        let processor = AnalyticsProcessor::new(
+            server.url("/api/v1/"),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            Some(10000),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+        processor.track_feature("feature_1");
+
+        // When
+        let result = processor.shutdown().await;
+
+        // Then
+        assert!(result.is_err());
+        flush_mock.assert_hits(ANALYTICS_FINAL_FLUSH_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn failed_flushes_are_retried_within_the_flush_interval() {
+        // Given
+        let server = MockServer::start();
+        let flush_mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/analytics/flags/");
+            then.status(503);
+        });
+        let processor = AnalyticsProcessor::new(
+            server.url("/api/v1/"),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            Some(50),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+
+        // When
+        processor.track_feature("feature_1");
+        sleep(std::time::Duration::from_millis(1000)).await;
+
+        // Then the retries do not back off beyond the interval
+        assert!(flush_mock.hits() >= 10);
+    }
+
+    #[test]
+    fn processor_is_idle_between_flushes() {
+        // Given a runtime whose clock only advances while all of its tasks are idle,
+        // so that a processor which never yields would keep it from ever finishing
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let _processor = AnalyticsProcessor::new(
+                    "http://localhost".to_string(),
+                    header::HeaderMap::new(),
+                    std::time::Duration::from_secs(10),
+                    Some(1000),
+                    10000,
+                    AnalyticsOverflowPolicy::DropNewest,
+                )
+                .await;
+
+                // When an hour passes without any evaluations
+                sleep(std::time::Duration::from_secs(60 * 60)).await;
+            });
+            done_tx.send(()).unwrap();
+        });
+
+        // Then
+        assert!(done_rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn processor_flushes_on_the_configured_timer() {
+        // Given
+        let server = MockServer::start();
+        let flush_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/analytics/flags/")
+                .json_body(serde_json::json!({"feature_1": 3}));
+            then.status(200);
+        });
+        let processor = AnalyticsProcessor::new(
+            server.url("/api/v1/"),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            Some(200),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+
+        // When
+        processor.track_feature("feature_1");
+        processor.track_feature("feature_1");
+        processor.track_feature("feature_1");
+
+        // Then
+        sleep(std::time::Duration::from_millis(100)).await;
+        flush_mock.assert_hits(0);
+        sleep(std::time::Duration::from_millis(200)).await;
+        flush_mock.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn failed_flush_is_retried_with_the_same_counts() {
+        // Given
+        let server = MockServer::start();
+        let mut failing_mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/analytics/flags/");
+            then.status(500);
+        });
+        let processor = AnalyticsProcessor::new(
+            server.url("/api/v1/"),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            Some(10),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+        processor.track_feature("feature_1");
+        processor.track_feature("feature_1");
+        sleep(std::time::Duration::from_millis(100)).await;
+
+        // Then the counts are kept while the API is failing
+        assert!(failing_mock.hits() >= 1);
+        assert_eq!(processor._analytics_data.read().await["feature_1"], 2);
+        assert!(processor.stats().failed_flushes >= 1);
+        assert_eq!(processor.stats().successful_flushes, 0);
+
+        // When the API recovers
+        failing_mock.delete();
+        let flush_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/analytics/flags/")
+                .json_body(serde_json::json!({"feature_1": 2}));
+            then.status(200);
+        });
+        for _ in 0..100 {
+            if processor.stats().successful_flushes > 0 {
+                break;
+            }
+            sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        // Then
+        flush_mock.assert();
+        assert!(processor._analytics_data.read().await.is_empty());
+        assert_eq!(processor.stats().successful_flushes, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn evaluations_are_counted_while_a_flush_is_in_flight() {
+        // Given an API that takes a while to respond
+        let server = MockServer::start();
+        let flush_mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/analytics/flags/");
+            then.status(200)
+                .delay(std::time::Duration::from_millis(500));
+        });
+        let processor = AnalyticsProcessor::new(
+            server.url("/api/v1/"),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            Some(50),
+            10000,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+        processor.track_feature("feature_1");
+        sleep(std::time::Duration::from_millis(150)).await;
+
+        // When more evaluations than the channel holds are tracked during the flush
+        for _ in 0..3 {
+            for _ in 0..ANALYTICS_CHANNEL_CAPACITY / 2 {
+                processor.track_feature("feature_1");
+            }
+            sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        // Then
+        flush_mock.assert_hits(1);
+        assert_eq!(processor.stats().dropped_evaluations, 0);
+        assert_eq!(
+            processor._analytics_data.read().await["feature_1"] as usize,
+            3 * ANALYTICS_CHANNEL_CAPACITY / 2
+        );
+    }
+
+    #[tokio::test]
+    async fn pending_features_are_bounded_by_the_overflow_policy() {
+        for (overflow_policy, expected_feature) in [
+            (AnalyticsOverflowPolicy::DropNewest, "feature_1"),
+            (AnalyticsOverflowPolicy::DropPending, "feature_2"),
+        ] {
+            // Given
+            let processor = AnalyticsProcessor::new(
+                "http://localhost".to_string(),
+                header::HeaderMap::new(),
+                std::time::Duration::from_secs(10),
+                Some(10000),
+                1,
+                overflow_policy,
+            )
+            .await;
+
+            // When
+            processor.track_feature("feature_1");
+            sleep(std::time::Duration::from_millis(10)).await;
+            processor.track_feature("feature_2");
+            sleep(std::time::Duration::from_millis(10)).await;
+
+            // Then
+            let analytics_data = processor._analytics_data.read().await;
+            assert_eq!(analytics_data.len(), 1);
+            assert_eq!(analytics_data[expected_feature], 1);
+            assert_eq!(processor.stats().dropped_evaluations, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluations_tracked_after_shutdown_are_counted_as_dropped() {
+        // Given
+        let processor = AnalyticsProcessor::new(
+            "http://localhost".to_string(),
+            header::HeaderMap::new(),
+            std::time::Duration::from_secs(10),
+            None,
+            10,
+            AnalyticsOverflowPolicy::DropNewest,
+        )
+        .await;
+        processor.shutdown().await.unwrap();
+
+        // When
+        processor.track_feature("feature_1");
+
+        // Then
+        assert_eq!(processor.stats().dropped_evaluations, 1);
+    }
 }
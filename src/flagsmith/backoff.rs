@@ -0,0 +1,57 @@
+use rand::Rng;
+use std::time::Duration;
+
+// Exponential backoff with equal jitter: the n-th consecutive failure waits
+// between half and all of `initial * 2^(n-1)`, capped at `max`.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max: max.max(initial),
+        }
+    }
+
+    pub fn delay(&self, consecutive_failures: u32) -> Duration {
+        let exponent = consecutive_failures.saturating_sub(1).min(31);
+        let delay = self.initial.saturating_mul(1 << exponent).min(self.max);
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=delay - half)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_grows_exponentially_within_jitter_bounds() {
+        // Given
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(60));
+
+        // Then
+        for (failures, expected) in [(1, 100), (2, 200), (3, 400), (4, 800)] {
+            let delay = backoff.delay(failures);
+            assert!(delay >= Duration::from_millis(expected / 2));
+            assert!(delay <= Duration::from_millis(expected));
+        }
+    }
+
+    #[test]
+    fn delay_is_capped_at_max() {
+        // Given
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        // Then
+        for failures in [10, 32, u32::MAX] {
+            let delay = backoff.delay(failures);
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_secs(1));
+        }
+    }
+}
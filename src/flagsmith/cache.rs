@@ -0,0 +1,378 @@
+use super::context::IdentityContext;
+use super::models::Flags;
+use crate::error;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+type SharedResult = Option<Result<Flags, error::Error>>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    Environment,
+    Identity {
+        identifier: String,
+        // Serialized traits, sorted so that their order does not matter
+        traits: Vec<String>,
+        transient: bool,
+        environment_key: Option<String>,
+    },
+}
+
+impl CacheKey {
+    pub fn identity(context: &IdentityContext) -> CacheKey {
+        let mut traits: Vec<String> = context
+            .traits
+            .iter()
+            .map(|t| serde_json::to_string(t).unwrap_or_default())
+            .collect();
+        traits.sort();
+        CacheKey::Identity {
+            identifier: context.identifier.clone(),
+            traits,
+            transient: context.transient,
+            environment_key: context.environment_key.clone(),
+        }
+    }
+}
+
+enum Slot {
+    Ready {
+        flags: Flags,
+        expires_at: Instant,
+        // Position in `Entries::recency`
+        last_used: u64,
+    },
+    // A request for the key is in flight; its result is sent once it completes
+    Pending(watch::Receiver<SharedResult>),
+}
+
+#[derive(Default)]
+struct Entries {
+    slots: HashMap<CacheKey, Slot>,
+    // Ready keys ordered from least to most recently used
+    recency: BTreeMap<u64, CacheKey>,
+    clock: u64,
+}
+
+impl Entries {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(Slot::Ready { last_used, .. }) = self.slots.remove(key) {
+            self.recency.remove(&last_used);
+        }
+    }
+}
+
+// In-memory cache of flags fetched from the API, expiring entries after `ttl`
+// and evicting the least recently used ones beyond `max_entries`. Concurrent
+// misses for the same key share a single request.
+pub struct FlagsCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+// Removes the pending slot if the request is cancelled before it completes, so
+// that waiting callers fetch the flags themselves
+struct PendingGuard<'a> {
+    cache: &'a FlagsCache,
+    key: &'a CacheKey,
+    rx: watch::Receiver<SharedResult>,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.cache.entries.lock().unwrap();
+        if let Some(Slot::Pending(rx)) = entries.slots.get(self.key) {
+            if rx.same_channel(&self.rx) {
+                entries.slots.remove(self.key);
+            }
+        }
+    }
+}
+
+impl FlagsCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        FlagsCache {
+            ttl,
+            max_entries: max_entries.max(1),
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    // Returns the cached flags for `key`, waiting for an in-flight request for the
+    // same key if there is one, or calls `fetch` otherwise. Errors are not cached.
+    pub async fn get_or_fetch<F, Fut>(&self, key: CacheKey, fetch: F) -> Result<Flags, error::Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Flags, error::Error>>,
+    {
+        let tx = loop {
+            let mut rx = match self.lookup(&key) {
+                Lookup::Hit(flags) => return Ok(flags),
+                Lookup::Wait(rx) => rx,
+                Lookup::Miss(tx) => break tx,
+            };
+            let shared =
+                rx.wait_for(|result| result.is_some())
+                    .await
+                    .map(|result| match result.as_ref() {
+                        Some(Ok(flags)) => Ok(flags.clone()),
+                        Some(Err(e)) => Err(e.duplicate()),
+                        None => unreachable!(),
+                    });
+            if let Ok(result) = shared {
+                return result;
+            }
+            // The request was cancelled, try again
+        };
+
+        let guard = PendingGuard {
+            cache: self,
+            key: &key,
+            rx: tx.subscribe(),
+        };
+        let result = fetch().await;
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.slots.remove(&key);
+            if let Ok(flags) = &result {
+                let last_used = entries.tick();
+                entries.slots.insert(
+                    key.clone(),
+                    Slot::Ready {
+                        flags: flags.clone(),
+                        expires_at: Instant::now() + self.ttl,
+                        last_used,
+                    },
+                );
+                entries.recency.insert(last_used, key.clone());
+                while entries.recency.len() > self.max_entries {
+                    let (_, evicted) = entries.recency.pop_first().unwrap();
+                    entries.slots.remove(&evicted);
+                }
+            }
+        }
+        drop(guard);
+        tx.send_replace(Some(match &result {
+            Ok(flags) => Ok(flags.clone()),
+            Err(e) => Err(e.duplicate()),
+        }));
+        result
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        let tick = entries.tick();
+        let Entries { slots, recency, .. } = &mut *entries;
+        match slots.get_mut(key) {
+            Some(Slot::Ready {
+                flags,
+                expires_at,
+                last_used,
+            }) if *expires_at > Instant::now() => {
+                recency.remove(last_used);
+                recency.insert(tick, key.clone());
+                *last_used = tick;
+                return Lookup::Hit(flags.clone());
+            }
+            Some(Slot::Pending(rx)) if rx.has_changed().is_ok() => {
+                return Lookup::Wait(rx.clone());
+            }
+            _ => {}
+        }
+        entries.remove(key);
+        let (tx, rx) = watch::channel(None);
+        entries.slots.insert(key.clone(), Slot::Pending(rx));
+        Lookup::Miss(tx)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().recency.len()
+    }
+}
+
+enum Lookup {
+    Hit(Flags),
+    Wait(watch::Receiver<SharedResult>),
+    Miss(watch::Sender<SharedResult>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flagsmith::models::SDKTrait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    fn flags(value: &str) -> Flags {
+        Flags::from_api_flags(
+            &vec![serde_json::json!({
+                "feature_state_value": value,
+                "feature": {"name": "feature1", "id": 1},
+                "enabled": true
+            })],
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn value(flags: Result<Flags, error::Error>) -> String {
+        flags
+            .unwrap()
+            .get_feature_value_as_string("feature1")
+            .unwrap()
+    }
+
+    fn identity_key(identifier: &str) -> CacheKey {
+        CacheKey::identity(&IdentityContext::new(identifier))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entries_expire_after_ttl() {
+        // Given
+        let cache = FlagsCache::new(Duration::from_secs(10), 10);
+        let fetches = AtomicU32::new(0);
+        let fetch = || async {
+            let n = fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(flags(&n.to_string()))
+        };
+
+        // When
+        let first = cache.get_or_fetch(CacheKey::Environment, fetch).await;
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let cached = cache.get_or_fetch(CacheKey::Environment, fetch).await;
+        tokio::time::advance(Duration::from_secs(6)).await;
+        let expired = cache.get_or_fetch(CacheKey::Environment, fetch).await;
+
+        // Then
+        assert_eq!(value(first), "0");
+        assert_eq!(value(cached), "0");
+        assert_eq!(value(expired), "1");
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entry_is_evicted() {
+        // Given
+        let cache = FlagsCache::new(Duration::from_secs(60), 2);
+        let fetch = |v: &'static str| move || async move { Ok(flags(v)) };
+        cache
+            .get_or_fetch(identity_key("a"), fetch("a"))
+            .await
+            .unwrap();
+        cache
+            .get_or_fetch(identity_key("b"), fetch("b"))
+            .await
+            .unwrap();
+        cache
+            .get_or_fetch(identity_key("a"), fetch("a2"))
+            .await
+            .unwrap();
+
+        // When
+        cache
+            .get_or_fetch(identity_key("c"), fetch("c"))
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            value(cache.get_or_fetch(identity_key("a"), fetch("a3")).await),
+            "a"
+        );
+        assert_eq!(
+            value(cache.get_or_fetch(identity_key("b"), fetch("b2")).await),
+            "b2"
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_a_single_fetch() {
+        // Given
+        let cache = Arc::new(FlagsCache::new(Duration::from_secs(60), 10));
+        let fetches = Arc::new(AtomicU32::new(0));
+
+        // When
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let fetches = Arc::clone(&fetches);
+                tokio::spawn(async move {
+                    cache
+                        .get_or_fetch(identity_key("user"), || async move {
+                            fetches.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Err(error::Error::Http {
+                                status: reqwest::StatusCode::BAD_GATEWAY,
+                                body: String::new(),
+                            })
+                        })
+                        .await
+                })
+            })
+            .collect();
+        let mut results = vec![];
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+
+        // Then
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert!(matches!(result, Err(error::Error::Http { status, .. }) if status == 502));
+        }
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn identity_key_ignores_trait_order() {
+        // Given
+        let trait_a = serde_json::from_value::<SDKTrait>(
+            serde_json::json!({"trait_key": "a", "trait_value": 1}),
+        )
+        .unwrap();
+        let trait_b = serde_json::from_value::<SDKTrait>(
+            serde_json::json!({"trait_key": "b", "trait_value": "x"}),
+        )
+        .unwrap();
+
+        let trait_a_changed = serde_json::from_value::<SDKTrait>(
+            serde_json::json!({"trait_key": "a", "trait_value": 2}),
+        )
+        .unwrap();
+
+        let key = |identifier: &str, traits: Vec<SDKTrait>| {
+            CacheKey::identity(&IdentityContext::new(identifier).with_traits(traits))
+        };
+
+        // Then
+        assert_eq!(
+            key("user", vec![trait_a.clone(), trait_b.clone()]),
+            key("user", vec![trait_b, trait_a.clone()])
+        );
+        assert_ne!(key("user", vec![trait_a.clone()]), key("user", vec![]));
+        assert_ne!(
+            key("user", vec![trait_a.clone()]),
+            key("user", vec![trait_a_changed])
+        );
+        assert_ne!(
+            key("user", vec![trait_a.clone()]),
+            key("other", vec![trait_a])
+        );
+        assert_ne!(
+            identity_key("user"),
+            CacheKey::identity(&IdentityContext::new("user").with_environment_key("ser.other"))
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use super::models::Flag;
+use flagsmith_flag_engine::environments::Environment;
+use std::collections::HashMap;
+
+// Emitted to subscribers whenever the environment is refreshed
+#[derive(Clone, Debug)]
+pub enum EnvironmentEvent {
+    // A refresh replaced the environment
+    Updated(EnvironmentChange),
+    // A refresh failed; the previous environment is still being served
+    RefreshFailed { message: String },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EnvironmentChange {
+    // Environment flags whose enabled state or value changed, or which were
+    // added or removed, sorted by feature name
+    pub changed_flags: Vec<FlagChange>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FlagChange {
+    pub feature_name: String,
+    // `None` if the feature did not exist before the refresh
+    pub previous: Option<Flag>,
+    // `None` if the feature was removed by the refresh
+    pub current: Option<Flag>,
+}
+
+impl EnvironmentChange {
+    pub fn between(previous: Option<&Environment>, current: &Environment) -> Self {
+        let mut previous_flags = previous.map(environment_flags).unwrap_or_default();
+        let mut changed_flags = vec![];
+        for (feature_name, current) in environment_flags(current) {
+            let previous = previous_flags.remove(&feature_name);
+            let unchanged = previous.as_ref().is_some_and(|previous| {
+                previous.enabled == current.enabled && previous.value == current.value
+            });
+            if !unchanged {
+                changed_flags.push(FlagChange {
+                    feature_name,
+                    previous,
+                    current: Some(current),
+                });
+            }
+        }
+        for (feature_name, previous) in previous_flags {
+            changed_flags.push(FlagChange {
+                feature_name,
+                previous: Some(previous),
+                current: None,
+            });
+        }
+        changed_flags.sort_by(|a, b| a.feature_name.cmp(&b.feature_name));
+        EnvironmentChange { changed_flags }
+    }
+
+    pub fn get(&self, feature_name: &str) -> Option<&FlagChange> {
+        self.changed_flags
+            .iter()
+            .find(|change| change.feature_name == feature_name)
+    }
+}
+
+pub(crate) fn environment_flag(environment: &Environment, feature_name: &str) -> Option<Flag> {
+    environment
+        .feature_states
+        .iter()
+        .find(|feature_state| feature_state.feature.name == feature_name)
+        .map(|feature_state| Flag::from_feature_state(feature_state.clone(), None))
+}
+
+fn environment_flags(environment: &Environment) -> HashMap<String, Flag> {
+    environment
+        .feature_states
+        .iter()
+        .map(|feature_state| {
+            (
+                feature_state.feature.name.clone(),
+                Flag::from_feature_state(feature_state.clone(), None),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment(feature_states: serde_json::Value) -> Environment {
+        serde_json::from_value(serde_json::json!({
+            "api_key": "B62qaMZNwfiqT76p38ggrQ",
+            "project": {
+                "name": "Test project",
+                "organisation": {
+                    "feature_analytics": false,
+                    "name": "Test Org",
+                    "id": 1,
+                    "persist_trait_data": true,
+                    "stop_serving_flags": false
+                },
+                "id": 1,
+                "hide_disabled_flags": false,
+                "segments": []
+            },
+            "id": 1,
+            "feature_states": feature_states
+        }))
+        .unwrap()
+    }
+
+    fn feature_state(id: u32, name: &str, enabled: bool, value: &str) -> serde_json::Value {
+        serde_json::json!({
+            "multivariate_feature_state_values": [],
+            "feature_state_value": value,
+            "django_id": id,
+            "feature": {"name": name, "type": "STANDARD", "id": id},
+            "enabled": enabled
+        })
+    }
+
+    #[test]
+    fn between_reports_changed_added_and_removed_flags() {
+        // Given
+        let previous = environment(serde_json::json!([
+            feature_state(1, "unchanged", true, "a"),
+            feature_state(2, "toggled", false, "a"),
+            feature_state(3, "new_value", true, "a"),
+            feature_state(4, "removed", true, "a"),
+        ]));
+        let current = environment(serde_json::json!([
+            feature_state(1, "unchanged", true, "a"),
+            feature_state(2, "toggled", true, "a"),
+            feature_state(3, "new_value", true, "b"),
+            feature_state(5, "added", true, "a"),
+        ]));
+
+        // When
+        let change = EnvironmentChange::between(Some(&previous), &current);
+
+        // Then
+        let names: Vec<&str> = change
+            .changed_flags
+            .iter()
+            .map(|change| change.feature_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["added", "new_value", "removed", "toggled"]);
+        assert!(change.get("added").unwrap().previous.is_none());
+        assert!(change.get("removed").unwrap().current.is_none());
+        let toggled = change.get("toggled").unwrap();
+        assert!(!toggled.previous.as_ref().unwrap().enabled);
+        assert!(toggled.current.as_ref().unwrap().enabled);
+    }
+
+    #[test]
+    fn between_reports_all_flags_without_previous_environment() {
+        // Given
+        let current = environment(serde_json::json!([feature_state(1, "feature", true, "a")]));
+
+        // When
+        let change = EnvironmentChange::between(None, &current);
+
+        // Then
+        assert_eq!(change.changed_flags.len(), 1);
+        assert!(change.get("feature").unwrap().previous.is_none());
+    }
+}
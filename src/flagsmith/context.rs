@@ -0,0 +1,196 @@
+use super::models::SDKTrait;
+use flagsmith_flag_engine::identities::Trait;
+use flagsmith_flag_engine::types::FlagsmithValue;
+
+// Describes the identity to evaluate flags for, accepted by every identity
+// evaluation method. Tuples matching the previous parameter lists convert into
+// it, so that `get_identity_flags((identifier, traits, transient))` and
+// `get_identity_segments((identifier, traits))` keep working.
+// # Example
+// ```
+// use flagsmith::flagsmith::context::IdentityContext;
+// use flagsmith_flag_engine::types::{FlagsmithValue, FlagsmithValueType};
+// let context = IdentityContext::new("user_identifier")
+//     .with_trait(
+//         "plan",
+//         FlagsmithValue {
+//             value: "premium".to_string(),
+//             value_type: FlagsmithValueType::String,
+//         },
+//     )
+//     .transient(true);
+// ```
+#[derive(Clone, Debug, Default)]
+pub struct IdentityContext {
+    pub identifier: String,
+    pub traits: Vec<SDKTrait>,
+    // Do not persist the identity, nor any of its traits, in Flagsmith. Only
+    // applies to remote evaluation.
+    pub transient: bool,
+    // Evaluate the identity in another environment than the client's. Such
+    // identities are always evaluated remotely, since only the client's
+    // environment is held locally.
+    pub environment_key: Option<String>,
+}
+
+impl IdentityContext {
+    pub fn new(identifier: impl Into<String>) -> Self {
+        IdentityContext {
+            identifier: identifier.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_trait(mut self, trait_key: impl Into<String>, trait_value: FlagsmithValue) -> Self {
+        self.traits
+            .push(SDKTrait::new(trait_key.into(), trait_value));
+        self
+    }
+
+    // Adds a trait that is used for this evaluation but not persisted in Flagsmith
+    pub fn with_transient_trait(
+        mut self,
+        trait_key: impl Into<String>,
+        trait_value: FlagsmithValue,
+    ) -> Self {
+        self.traits.push(SDKTrait::new_with_transient(
+            trait_key.into(),
+            trait_value,
+            true,
+        ));
+        self
+    }
+
+    // Adds traits of either type, `SDKTrait` or engine `Trait`
+    pub fn with_traits<T: Into<SDKTrait>>(mut self, traits: impl IntoIterator<Item = T>) -> Self {
+        self.traits.extend(traits.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn transient(mut self, transient: bool) -> Self {
+        self.transient = transient;
+        self
+    }
+
+    pub fn with_environment_key(mut self, environment_key: impl Into<String>) -> Self {
+        self.environment_key = Some(environment_key.into());
+        self
+    }
+
+    pub(crate) fn engine_traits(&self) -> Vec<Trait> {
+        self.traits.iter().cloned().map(Into::into).collect()
+    }
+}
+
+impl From<&str> for IdentityContext {
+    fn from(identifier: &str) -> Self {
+        IdentityContext::new(identifier)
+    }
+}
+
+impl From<String> for IdentityContext {
+    fn from(identifier: String) -> Self {
+        IdentityContext::new(identifier)
+    }
+}
+
+// The parameters previously taken by `get_identity_flags`
+impl From<(&str, Option<Vec<SDKTrait>>, Option<bool>)> for IdentityContext {
+    fn from((identifier, traits, transient): (&str, Option<Vec<SDKTrait>>, Option<bool>)) -> Self {
+        (identifier.to_string(), traits, transient).into()
+    }
+}
+
+impl From<(String, Option<Vec<SDKTrait>>, Option<bool>)> for IdentityContext {
+    fn from(
+        (identifier, traits, transient): (String, Option<Vec<SDKTrait>>, Option<bool>),
+    ) -> Self {
+        IdentityContext::new(identifier)
+            .with_traits(traits.unwrap_or_default())
+            .transient(transient.unwrap_or(false))
+    }
+}
+
+// The parameters previously taken by `get_identity_segments`
+impl From<(&str, Option<Vec<Trait>>)> for IdentityContext {
+    fn from((identifier, traits): (&str, Option<Vec<Trait>>)) -> Self {
+        (identifier.to_string(), traits).into()
+    }
+}
+
+impl From<(String, Option<Vec<Trait>>)> for IdentityContext {
+    fn from((identifier, traits): (String, Option<Vec<Trait>>)) -> Self {
+        IdentityContext::new(identifier).with_traits(traits.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flagsmith_flag_engine::types::FlagsmithValueType;
+
+    fn value(value: &str) -> FlagsmithValue {
+        FlagsmithValue {
+            value: value.to_string(),
+            value_type: FlagsmithValueType::String,
+        }
+    }
+
+    #[test]
+    fn previous_parameter_lists_convert_into_context() {
+        // Given
+        let sdk_traits = vec![SDKTrait::new_with_transient(
+            "key".to_string(),
+            value("value"),
+            true,
+        )];
+        let engine_traits = vec![Trait {
+            trait_key: "key".to_string(),
+            trait_value: value("value"),
+        }];
+
+        // When
+        let from_flags_parameters: IdentityContext =
+            ("identity", Some(sdk_traits), Some(true)).into();
+        let from_segments_parameters: IdentityContext =
+            ("identity".to_string(), Some(engine_traits)).into();
+
+        // Then
+        assert_eq!(from_flags_parameters.identifier, "identity");
+        assert!(from_flags_parameters.transient);
+        assert!(from_flags_parameters.traits[0].transient);
+        assert_eq!(from_segments_parameters.identifier, "identity");
+        assert!(!from_segments_parameters.transient);
+        assert!(!from_segments_parameters.traits[0].transient);
+        assert_eq!(
+            from_segments_parameters.engine_traits()[0].trait_value,
+            value("value")
+        );
+    }
+
+    #[test]
+    fn builder_sets_every_field() {
+        // When
+        let context = IdentityContext::new("identity")
+            .with_trait("persisted", value("a"))
+            .with_transient_trait("transient", value("b"))
+            .transient(true)
+            .with_environment_key("ser.other_environment");
+
+        // Then
+        assert_eq!(context.identifier, "identity");
+        assert_eq!(
+            context
+                .traits
+                .iter()
+                .map(|t| (t.trait_key.as_str(), t.transient))
+                .collect::<Vec<_>>(),
+            vec![("persisted", false), ("transient", true)]
+        );
+        assert!(context.transient);
+        assert_eq!(
+            context.environment_key.as_deref(),
+            Some("ser.other_environment")
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use super::store::EnvironmentMetadata;
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Persists the last environment document fetched from the API so that a restarted
+// client can evaluate flags before (or without) reaching the API. The document's
+// metadata is kept in a file next to it, so that the first request after a restart
+// can be conditional.
+pub struct EnvironmentCache {
+    path: PathBuf,
+    metadata_path: PathBuf,
+    max_age: Duration,
+}
+
+impl EnvironmentCache {
+    pub fn new(path: PathBuf, max_age: Duration) -> Self {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".metadata");
+        EnvironmentCache {
+            metadata_path: path.with_file_name(file_name),
+            path,
+            max_age,
+        }
+    }
+
+    // Returns the cached document and its metadata, or `None` if the document is
+    // missing, unreadable or was last written or touched longer than `max_age` ago
+    pub async fn load(&self) -> Option<(serde_json::Value, EnvironmentMetadata)> {
+        let metadata = tokio::fs::metadata(&self.path).await.ok()?;
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or_default();
+        if age > self.max_age {
+            debug!(
+                "Ignoring cached environment {}, written {}s ago",
+                self.path.display(),
+                age.as_secs()
+            );
+            return None;
+        }
+        let contents = tokio::fs::read(&self.path).await.ok()?;
+        match serde_json::from_slice::<serde_json::Value>(&contents) {
+            Ok(document) => {
+                let metadata = self
+                    .load_metadata()
+                    .await
+                    .unwrap_or_else(|| EnvironmentMetadata {
+                        updated_at: document["updated_at"]
+                            .as_str()
+                            .map(|value| value.to_string()),
+                        ..Default::default()
+                    });
+                Some((document, metadata))
+            }
+            Err(e) => {
+                warn!(
+                    "Ignoring invalid cached environment {}: {}",
+                    self.path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    async fn load_metadata(&self) -> Option<EnvironmentMetadata> {
+        let contents = tokio::fs::read(&self.metadata_path).await.ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    // Writes the document before its metadata, so that an interrupted write never
+    // pairs an older document with the ETag of a newer one
+    pub async fn store(
+        &self,
+        document: &[u8],
+        metadata: &EnvironmentMetadata,
+    ) -> std::io::Result<()> {
+        write_atomically(&self.path, document).await?;
+        write_atomically(&self.metadata_path, &serde_json::to_vec(metadata)?).await
+    }
+
+    // Marks the cached document as fresh once the API confirmed that it is still
+    // current, since its age is measured from its modification time
+    pub async fn touch(&self) -> std::io::Result<()> {
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .await?
+            .into_std()
+            .await;
+        tokio::task::spawn_blocking(move || file.set_modified(SystemTime::now())).await?
+    }
+}
+
+// Writes to a temporary file next to `path` and renames it, so that a crash never
+// leaves a partially written file behind. The temporary file name is unique so that
+// concurrent writers, in this or another process, never share it.
+pub async fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(
+        ".{}.{:016x}.tmp",
+        std::process::id(),
+        rand::random::<u64>()
+    ));
+    let temp_path = path.with_file_name(file_name);
+    let result = match tokio::fs::write(&temp_path, contents).await {
+        Ok(()) => tokio::fs::rename(&temp_path, path).await,
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("flagsmith-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn stored_document_is_loaded_with_its_metadata() {
+        // Given
+        let path = cache_path("stored");
+        let cache = EnvironmentCache::new(path.clone(), Duration::from_secs(60));
+        let document = serde_json::json!({"api_key": "key"});
+        let metadata = EnvironmentMetadata {
+            etag: Some("\"v1\"".to_string()),
+            ..Default::default()
+        };
+
+        // When
+        cache
+            .store(&serde_json::to_vec(&document).unwrap(), &metadata)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(cache.load().await, Some((document, metadata)));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&cache.metadata_path);
+    }
+
+    #[tokio::test]
+    async fn touch_refreshes_the_document_age() {
+        // Given
+        let path = cache_path("touched");
+        let cache = EnvironmentCache::new(path.clone(), Duration::from_millis(200));
+        cache
+            .store(b"{}", &EnvironmentMetadata::default())
+            .await
+            .unwrap();
+        let written_at = std::fs::metadata(&path).unwrap().modified().unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(cache.load().await, None);
+
+        // When
+        cache.touch().await.unwrap();
+
+        // Then
+        assert!(cache.load().await.is_some());
+        assert!(std::fs::metadata(&path).unwrap().modified().unwrap() > written_at);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&cache.metadata_path);
+    }
+
+    #[tokio::test]
+    async fn stale_missing_or_invalid_documents_are_ignored() {
+        // Given
+        let path = cache_path("stale");
+        let stale_cache = EnvironmentCache::new(path.clone(), Duration::ZERO);
+        let cache = EnvironmentCache::new(path.clone(), Duration::from_secs(60));
+
+        // Then
+        assert_eq!(cache.load().await, None);
+        cache
+            .store(b"{}", &EnvironmentMetadata::default())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(stale_cache.load().await, None);
+        cache
+            .store(b"{not json", &EnvironmentMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(cache.load().await, None);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&cache.metadata_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_writes_do_not_share_a_temporary_file() {
+        // Given
+        let path = cache_path("concurrent");
+        let documents: Vec<Vec<u8>> = (0..16)
+            .map(|i| serde_json::to_vec(&serde_json::json!({ "writer": i })).unwrap())
+            .collect();
+
+        // When
+        let writes: Vec<_> = documents
+            .iter()
+            .cloned()
+            .map(|document| {
+                let path = path.clone();
+                tokio::spawn(async move { write_atomically(&path, &document).await })
+            })
+            .collect();
+
+        // Then
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+        assert!(documents.contains(&std::fs::read(&path).unwrap()));
+        let leftovers = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                name.starts_with(&path.file_name().unwrap().to_string_lossy().to_string())
+                    && name.ends_with(".tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
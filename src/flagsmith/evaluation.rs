@@ -0,0 +1,459 @@
+use super::models::{EvaluationReason, Flag};
+use flagsmith_flag_engine::engine;
+use flagsmith_flag_engine::environments::Environment;
+use flagsmith_flag_engine::features::FeatureState;
+use flagsmith_flag_engine::identities::{Identity, Trait};
+use flagsmith_flag_engine::segments::evaluator::get_identity_segments;
+use flagsmith_flag_engine::types::FlagsmithValue;
+use flagsmith_flag_engine::utils::hashing::get_hashed_percentage_for_object_ids;
+use std::collections::HashMap;
+
+// A single decision taken while evaluating a feature for an identity
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvaluationStep {
+    // The environment defines the feature
+    Environment {
+        enabled: bool,
+        value: FlagsmithValue,
+    },
+    // The identity matches a segment overriding the feature; `applied` is false if
+    // the override of a higher priority segment was kept instead
+    SegmentOverride {
+        segment_name: String,
+        priority: Option<u32>,
+        applied: bool,
+    },
+    // The identity overrides the feature directly
+    IdentityOverride {
+        enabled: bool,
+        value: FlagsmithValue,
+    },
+    // A multivariate option was picked using the identity's hashed percentage
+    MultivariateSplit {
+        percentage: f32,
+        value: FlagsmithValue,
+    },
+    // The feature is disabled and the project hides disabled flags
+    HiddenDisabled,
+    // The feature was not found and `default_flag_handler` provided the flag
+    HandlerDefault,
+}
+
+// The full decision trace of a feature evaluated for an identity
+#[derive(Clone, Debug)]
+pub struct EvaluationTrace {
+    pub feature_name: String,
+    pub steps: Vec<EvaluationStep>,
+    // `None` if the feature is not returned for the identity
+    pub flag: Option<Flag>,
+}
+
+// Where the engine can take feature states from, keyed by feature state uuid.
+// The engine keeps feature states whole, so their uuid identifies the originating
+// override. Built once per evaluation rather than scanning the overrides per flag.
+struct Origins<'a>(HashMap<&'a str, EvaluationReason>);
+
+impl<'a> Origins<'a> {
+    fn new(environment: &'a Environment, identity: &'a Identity) -> Self {
+        let mut origins = HashMap::new();
+        for segment in &environment.project.segments {
+            for feature_state in &segment.feature_states {
+                origins
+                    .entry(feature_state.featurestate_uuid.as_str())
+                    .or_insert_with(|| EvaluationReason::SegmentOverride {
+                        segment_name: segment.name.clone(),
+                    });
+            }
+        }
+        // Identity overrides take precedence over segments sharing the uuid
+        for feature_state in &identity.identity_features {
+            origins.insert(
+                feature_state.featurestate_uuid.as_str(),
+                EvaluationReason::IdentityOverride,
+            );
+        }
+        Origins(origins)
+    }
+
+    // Returns where the engine took `feature_state` from
+    fn of(&self, feature_state: &FeatureState) -> EvaluationReason {
+        self.0
+            .get(feature_state.featurestate_uuid.as_str())
+            .cloned()
+            .unwrap_or(EvaluationReason::Default)
+    }
+}
+
+// Returns the identity's hashed percentage if it selects one of the multivariate
+// options rather than the control value
+fn multivariate_percentage(feature_state: &FeatureState, identity_key: &str) -> Option<f32> {
+    if feature_state.multivariate_feature_state_values.is_empty() {
+        return None;
+    }
+    // Same object ids as `FeatureState::get_value` hashes to pick the option
+    let object_id = match feature_state.django_id {
+        Some(django_id) => django_id.to_string(),
+        None => feature_state.featurestate_uuid.clone(),
+    };
+    let percentage = get_hashed_percentage_for_object_ids(vec![&object_id, identity_key], 1);
+    // The options are allocated consecutive ranges starting at 0
+    let allocated: f32 = feature_state
+        .multivariate_feature_state_values
+        .iter()
+        .map(|value| value.percentage_allocation)
+        .sum();
+    (percentage < allocated).then_some(percentage)
+}
+
+fn to_flag(origins: &Origins, feature_state: FeatureState, identity_key: &str) -> Flag {
+    let origin = origins.of(&feature_state);
+    let reason = match multivariate_percentage(&feature_state, identity_key) {
+        Some(percentage) => EvaluationReason::MultivariateSplit {
+            percentage,
+            origin: Box::new(origin),
+        },
+        None => origin,
+    };
+    let mut flag = Flag::from_feature_state(feature_state, Some(identity_key));
+    flag.reason = reason;
+    flag
+}
+
+// Returns the flags of an identity, each carrying the reason for its value
+pub(crate) fn identity_flags(
+    environment: &Environment,
+    identity: &Identity,
+    override_traits: Option<&Vec<Trait>>,
+) -> Vec<Flag> {
+    let identity_key = identity.composite_key();
+    let origins = Origins::new(environment, identity);
+    engine::get_identity_feature_states(environment, identity, override_traits)
+        .into_iter()
+        .map(|feature_state| to_flag(&origins, feature_state, &identity_key))
+        .collect()
+}
+
+// Returns how a single feature is evaluated for an identity, without falling back
+// to the default flag handler
+pub(crate) fn explain_identity_flag(
+    environment: &Environment,
+    identity: &Identity,
+    override_traits: Option<&Vec<Trait>>,
+    feature_name: &str,
+) -> EvaluationTrace {
+    let identity_key = identity.composite_key();
+    let is_feature = |feature_state: &&FeatureState| feature_state.feature.name == feature_name;
+    let mut steps = vec![];
+    for feature_state in environment.feature_states.iter().filter(is_feature) {
+        steps.push(EvaluationStep::Environment {
+            enabled: feature_state.enabled,
+            value: feature_state.get_value(None),
+        });
+    }
+
+    // The segment override the engine picks once identity overrides are left out
+    let mut segment_identity = identity.clone();
+    segment_identity.identity_features.clear();
+    let segment_feature_state = engine::get_identity_feature_state(
+        environment,
+        &segment_identity,
+        feature_name,
+        override_traits,
+    )
+    .ok();
+    for segment in get_identity_segments(environment, identity, override_traits) {
+        for feature_state in segment.feature_states.iter().filter(is_feature) {
+            steps.push(EvaluationStep::SegmentOverride {
+                segment_name: segment.name.clone(),
+                priority: feature_state
+                    .feature_segment
+                    .as_ref()
+                    .map(|feature_segment| feature_segment.priority),
+                applied: segment_feature_state.as_ref().is_some_and(|applied| {
+                    applied.featurestate_uuid == feature_state.featurestate_uuid
+                }),
+            });
+        }
+    }
+
+    for feature_state in identity.identity_features.iter().filter(is_feature) {
+        steps.push(EvaluationStep::IdentityOverride {
+            enabled: feature_state.enabled,
+            value: feature_state.get_value(None),
+        });
+    }
+
+    let flag =
+        engine::get_identity_feature_state(environment, identity, feature_name, override_traits)
+            .ok()
+            .and_then(|feature_state| {
+                let enabled = feature_state.enabled;
+                let flag = to_flag(
+                    &Origins::new(environment, identity),
+                    feature_state,
+                    &identity_key,
+                );
+                if let EvaluationReason::MultivariateSplit { percentage, .. } = flag.reason {
+                    steps.push(EvaluationStep::MultivariateSplit {
+                        percentage,
+                        value: flag.value.clone(),
+                    });
+                }
+                if environment.project.hide_disabled_flags && !enabled {
+                    steps.push(EvaluationStep::HiddenDisabled);
+                    return None;
+                }
+                Some(flag)
+            });
+    EvaluationTrace {
+        feature_name: feature_name.to_string(),
+        steps,
+        flag,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn environment() -> Environment {
+        let feature = |id: u32, name: &str| json!({"name": name, "type": "STANDARD", "id": id});
+        serde_json::from_value(json!({
+            "api_key": "B62qaMZNwfiqT76p38ggrQ",
+            "project": {
+                "name": "Test project",
+                "organisation": {
+                    "feature_analytics": false,
+                    "name": "Test Org",
+                    "id": 1,
+                    "persist_trait_data": true,
+                    "stop_serving_flags": false
+                },
+                "id": 1,
+                "hide_disabled_flags": false,
+                "segments": [
+                    {
+                        "id": 1,
+                        "name": "beta_users",
+                        "rules": [{
+                            "type": "ALL",
+                            "rules": [],
+                            "conditions": [
+                                {"operator": "EQUAL", "property_": "beta", "value": "true"}
+                            ]
+                        }],
+                        "feature_states": [
+                            {
+                                "multivariate_feature_state_values": [],
+                                "feature_state_value": "segment-value",
+                                "django_id": 10,
+                                "feature": feature(1, "segmented"),
+                                "enabled": true,
+                                "feature_segment": {"priority": 1}
+                            },
+                            {
+                                "multivariate_feature_state_values": [
+                                    {
+                                        "id": 2,
+                                        "multivariate_feature_option": {"value": "segment-variant"},
+                                        "percentage_allocation": 100.0
+                                    }
+                                ],
+                                "feature_state_value": "segment-control",
+                                "django_id": 11,
+                                "feature": feature(2, "multivariate"),
+                                "enabled": true,
+                                "feature_segment": {"priority": 1}
+                            }
+                        ]
+                    }
+                ]
+            },
+            "id": 1,
+            "feature_states": [
+                {
+                    "multivariate_feature_state_values": [],
+                    "feature_state_value": "environment-value",
+                    "django_id": 1,
+                    "feature": feature(1, "segmented"),
+                    "enabled": false
+                },
+                {
+                    "multivariate_feature_state_values": [
+                        {
+                            "id": 1,
+                            "multivariate_feature_option": {"value": "variant"},
+                            "percentage_allocation": 100.0
+                        }
+                    ],
+                    "feature_state_value": "control",
+                    "django_id": 2,
+                    "feature": feature(2, "multivariate"),
+                    "enabled": true
+                },
+                {
+                    "multivariate_feature_state_values": [],
+                    "feature_state_value": "environment-value",
+                    "django_id": 3,
+                    "feature": feature(3, "overridden"),
+                    "enabled": true
+                },
+                {
+                    "multivariate_feature_state_values": [
+                        {
+                            "id": 3,
+                            "multivariate_feature_option": {"value": "variant"},
+                            "percentage_allocation": 0.0
+                        }
+                    ],
+                    "feature_state_value": "control",
+                    "django_id": 5,
+                    "feature": feature(4, "unallocated"),
+                    "enabled": true
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn identity() -> Identity {
+        let mut identity = Identity::new("user".to_string(), "B62qaMZNwfiqT76p38ggrQ".to_string());
+        identity.identity_features = vec![serde_json::from_value(json!({
+            "multivariate_feature_state_values": [],
+            "feature_state_value": "identity-value",
+            "django_id": 4,
+            "feature": {"name": "overridden", "type": "STANDARD", "id": 3},
+            "enabled": false
+        }))
+        .unwrap()];
+        identity
+    }
+
+    fn beta_traits() -> Vec<Trait> {
+        vec![serde_json::from_value(json!({"trait_key": "beta", "trait_value": "true"})).unwrap()]
+    }
+
+    #[test]
+    fn identity_flags_carry_the_reason_for_their_value() {
+        // Given
+        let environment = environment();
+        let identity = identity();
+        let traits = beta_traits();
+
+        // When
+        let flags: HashMap<String, Flag> = identity_flags(&environment, &identity, Some(&traits))
+            .into_iter()
+            .map(|flag| (flag.feature_name.clone(), flag))
+            .collect();
+
+        // Then
+        assert_eq!(
+            flags["segmented"].reason,
+            EvaluationReason::SegmentOverride {
+                segment_name: "beta_users".to_string()
+            }
+        );
+        assert_eq!(
+            flags["segmented"].value_as_string().unwrap(),
+            "segment-value"
+        );
+        assert!(matches!(
+            &flags["multivariate"].reason,
+            EvaluationReason::MultivariateSplit { origin, .. }
+                if **origin == EvaluationReason::SegmentOverride {
+                    segment_name: "beta_users".to_string()
+                }
+        ));
+        assert_eq!(
+            flags["multivariate"].value_as_string().unwrap(),
+            "segment-variant"
+        );
+        assert_eq!(flags["unallocated"].reason, EvaluationReason::Default);
+        assert_eq!(flags["unallocated"].value_as_string().unwrap(), "control");
+        assert_eq!(
+            flags["overridden"].reason,
+            EvaluationReason::IdentityOverride
+        );
+        assert_eq!(
+            flags["overridden"].value_as_string().unwrap(),
+            "identity-value"
+        );
+    }
+
+    #[test]
+    fn identity_flags_match_the_engine() {
+        // Given
+        let environment = environment();
+        let identity = identity();
+        let traits = beta_traits();
+
+        // When
+        let flags = identity_flags(&environment, &identity, Some(&traits));
+
+        // Then
+        let engine_flags =
+            engine::get_identity_feature_states(&environment, &identity, Some(&traits));
+        assert_eq!(flags.len(), engine_flags.len());
+        for feature_state in engine_flags {
+            let flag = flags
+                .iter()
+                .find(|flag| flag.feature_name == feature_state.feature.name)
+                .unwrap();
+            assert_eq!(flag.enabled, feature_state.enabled);
+            assert_eq!(
+                flag.value,
+                feature_state.get_value(Some(&identity.composite_key()))
+            );
+        }
+    }
+
+    #[test]
+    fn explain_identity_flag_returns_every_step() {
+        // Given
+        let environment = environment();
+        let identity = identity();
+        let traits = beta_traits();
+
+        // When
+        let trace = explain_identity_flag(&environment, &identity, Some(&traits), "segmented");
+
+        // Then
+        assert_eq!(
+            trace.steps,
+            vec![
+                EvaluationStep::Environment {
+                    enabled: false,
+                    value: FlagsmithValue {
+                        value: "environment-value".to_string(),
+                        value_type: flagsmith_flag_engine::types::FlagsmithValueType::String,
+                    },
+                },
+                EvaluationStep::SegmentOverride {
+                    segment_name: "beta_users".to_string(),
+                    priority: Some(1),
+                    applied: true,
+                },
+            ]
+        );
+        assert!(trace.flag.unwrap().enabled);
+        let trace = explain_identity_flag(&environment, &identity, None, "multivariate");
+        assert!(matches!(
+            trace.steps.last().unwrap(),
+            EvaluationStep::MultivariateSplit { .. }
+        ));
+        assert!(matches!(
+            &trace.flag.unwrap().reason,
+            EvaluationReason::MultivariateSplit { origin, .. }
+                if **origin == EvaluationReason::Default
+        ));
+        let trace = explain_identity_flag(&environment, &identity, None, "unallocated");
+        assert_eq!(trace.steps.len(), 1);
+        assert_eq!(trace.flag.unwrap().reason, EvaluationReason::Default);
+        assert!(
+            explain_identity_flag(&environment, &identity, None, "missing")
+                .flag
+                .is_none()
+        );
+    }
+}
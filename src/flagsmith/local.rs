@@ -0,0 +1,219 @@
+use super::analytics::AnalyticsProcessor;
+use super::context::IdentityContext;
+use super::default_handler::DefaultHandler;
+use super::models::{self, Flags};
+use super::{evaluate_identity_flags, get_identity_model, DataStore, OfflineEnvironment};
+use crate::error;
+use flagsmith_flag_engine::environments::Environment;
+use flagsmith_flag_engine::identities::{Identity, Trait};
+use flagsmith_flag_engine::segments::evaluator::get_identity_segments;
+use flagsmith_flag_engine::segments::Segment;
+use log::warn;
+use std::sync::Arc;
+
+// Evaluates flags synchronously against the environment held locally (local
+// evaluation or offline handler), always reading the latest snapshot without
+// waiting on I/O or on other readers. Cheap to clone and shareable between
+// threads. Without a local environment, flags fall back to the offline
+// environment or `default_flag_handler` like the async `Flagsmith` methods, and
+// `Error::LocalEvaluationRequired` is returned otherwise. Must not outlive the
+// client, see `Flagsmith::local`.
+#[derive(Clone)]
+pub struct LocalEvaluator {
+    pub(super) environment_key: String,
+    pub(super) datastore: Arc<DataStore>,
+    // The offline handler's environment with `offline_fallback`
+    pub(super) offline_environment: OfflineEnvironment,
+    pub(super) analytics_processor: Option<AnalyticsProcessor>,
+    pub(super) default_flag_handler: Option<Arc<dyn DefaultHandler + Send + Sync>>,
+}
+
+impl LocalEvaluator {
+    // Returns the flags of the environment
+    pub fn environment_flags(&self) -> Result<Flags, error::Error> {
+        match self.datastore.environment() {
+            Some(environment) => Ok(self.get_environment_flags_from_document(&environment)),
+            None => self
+                .fallback_if_err(Err(error::Error::LocalEvaluationRequired), |environment| {
+                    self.get_environment_flags_from_document(environment)
+                }),
+        }
+    }
+
+    // Returns the flags of the given identity. Traits are only used for this
+    // evaluation and are not sent to the API.
+    pub fn identity_flags(
+        &self,
+        context: impl Into<IdentityContext>,
+    ) -> Result<Flags, error::Error> {
+        let context = context.into();
+        if !self.is_own_environment(&context) {
+            return self.default_if_err(Err(other_environment_error()));
+        }
+        match self.identity_snapshot(&context.identifier) {
+            Some((environment, identity_override)) => self.get_identity_flags_from_document(
+                &environment,
+                identity_override,
+                &context.identifier,
+                context.engine_traits(),
+            ),
+            None => self
+                .fallback_if_err(Err(error::Error::LocalEvaluationRequired), |environment| {
+                    self.get_offline_identity_flags(environment, &context)
+                }),
+        }
+    }
+
+    // Returns the segments that the given identity is part of
+    pub fn identity_segments(
+        &self,
+        context: impl Into<IdentityContext>,
+    ) -> Result<Vec<Segment>, error::Error> {
+        let context = context.into();
+        let (environment, identity_override) = self.own_identity_snapshot(&context)?;
+        let traits = context.engine_traits();
+        let identity_model = get_identity_model(
+            &environment,
+            identity_override,
+            &context.identifier,
+            traits.clone(),
+        );
+        let segments = get_identity_segments(&environment, &identity_model, Some(&traits));
+        Ok(segments)
+    }
+
+    // Returns whether the identity belongs to the environment held locally
+    pub(super) fn is_own_environment(&self, context: &IdentityContext) -> bool {
+        match &context.environment_key {
+            Some(environment_key) => *environment_key == self.environment_key,
+            None => true,
+        }
+    }
+
+    // Same as `identity_snapshot`, failing if the identity belongs to another
+    // environment or no environment is held locally
+    pub(super) fn own_identity_snapshot(
+        &self,
+        context: &IdentityContext,
+    ) -> Result<(Arc<Environment>, Option<Identity>), error::Error> {
+        if !self.is_own_environment(context) {
+            return Err(other_environment_error());
+        }
+        self.identity_snapshot(&context.identifier)
+            .ok_or(error::Error::LocalEvaluationRequired)
+    }
+
+    // Returns the current environment along with the override for the given identity
+    // (if any)
+    pub(super) fn identity_snapshot(
+        &self,
+        identifier: &str,
+    ) -> Option<(Arc<Environment>, Option<Identity>)> {
+        let snapshot = self.datastore.snapshot.load_full()?;
+        let identity_override = snapshot.identity_override(identifier);
+        Some((Arc::clone(&snapshot.environment), identity_override))
+    }
+
+    // Same as `identity_snapshot` for many identities, all from the same environment
+    pub(super) fn identities_snapshot<'a>(
+        &self,
+        identifiers: impl Iterator<Item = &'a str>,
+    ) -> Option<(Arc<Environment>, Vec<Option<Identity>>)> {
+        let snapshot = self.datastore.snapshot.load_full()?;
+        let identity_overrides = identifiers
+            .map(|identifier| snapshot.identity_override(identifier))
+            .collect();
+        Some((Arc::clone(&snapshot.environment), identity_overrides))
+    }
+
+    // Falls back to evaluating `from_document` against the offline environment, or
+    // to `default_flag_handler`, if the flags could not be retrieved
+    pub(super) fn fallback_if_err(
+        &self,
+        result: Result<Flags, error::Error>,
+        from_document: impl FnOnce(&Environment) -> Flags,
+    ) -> Result<Flags, error::Error> {
+        match result {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                let offline_environment = self.offline_environment.read().unwrap().clone();
+                match offline_environment {
+                    Some(environment) => {
+                        warn!("Serving flags from the offline environment: {}", e);
+                        Ok(from_document(&environment))
+                    }
+                    None => self.default_if_err(Err(e)),
+                }
+            }
+        }
+    }
+
+    // Falls back to `default_flag_handler` if the flags could not be retrieved
+    pub(super) fn default_if_err(
+        &self,
+        result: Result<Flags, error::Error>,
+    ) -> Result<Flags, error::Error> {
+        match result {
+            Err(e) if self.default_flag_handler.is_some() => Ok(Flags::from_error(
+                &e,
+                self.analytics_processor.clone(),
+                self.default_flag_handler.clone(),
+            )),
+            result => result,
+        }
+    }
+
+    pub(super) fn get_offline_identity_flags(
+        &self,
+        environment: &Environment,
+        context: &IdentityContext,
+    ) -> Flags {
+        let identity_override = environment
+            .identity_overrides
+            .iter()
+            .find(|identity| identity.identifier == context.identifier)
+            .cloned();
+        evaluate_identity_flags(
+            environment,
+            identity_override,
+            &context.identifier,
+            context.engine_traits(),
+            self.analytics_processor.clone(),
+            self.default_flag_handler.clone(),
+        )
+    }
+
+    pub(super) fn get_environment_flags_from_document(
+        &self,
+        environment: &Environment,
+    ) -> models::Flags {
+        models::Flags::from_feature_states(
+            &environment.feature_states,
+            self.analytics_processor.clone(),
+            self.default_flag_handler.clone(),
+            None,
+        )
+    }
+
+    pub(super) fn get_identity_flags_from_document(
+        &self,
+        environment: &Environment,
+        identity_override: Option<Identity>,
+        identifier: &str,
+        traits: Vec<Trait>,
+    ) -> Result<Flags, error::Error> {
+        Ok(evaluate_identity_flags(
+            environment,
+            identity_override,
+            identifier,
+            traits,
+            self.analytics_processor.clone(),
+            self.default_flag_handler.clone(),
+        ))
+    }
+}
+
+// Does not include the key, which may be a server-side key
+fn other_environment_error() -> error::Error {
+    error::Error::Client("the environment of the identity is not held locally".to_string())
+}
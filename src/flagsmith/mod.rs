@@ -1,30 +1,52 @@
 use self::analytics::AnalyticsProcessor;
+pub use self::analytics::{AnalyticsOverflowPolicy, AnalyticsStats};
+use self::backoff::Backoff;
+use self::cache::{CacheKey, FlagsCache};
+use self::changes::{EnvironmentChange, EnvironmentEvent};
+use self::context::IdentityContext;
+use self::environment_cache::EnvironmentCache;
+use self::evaluation::EvaluationTrace;
+pub use self::local::LocalEvaluator;
 use self::models::Flags;
+use self::realtime::RealtimeListener;
+use self::store::{EnvironmentMetadata, EnvironmentStore, StoredEnvironment};
 use super::error;
-use flagsmith_flag_engine::engine;
-use flagsmith_flag_engine::environments::builders::build_environment_struct;
+use super::error::ConfigurationError;
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Utc};
 use flagsmith_flag_engine::environments::Environment;
 use flagsmith_flag_engine::identities::{Identity, Trait};
-use flagsmith_flag_engine::segments::evaluator::get_identity_segments;
 use flagsmith_flag_engine::segments::Segment;
-use log::debug;
-use models::SDKTrait;
+use futures_util::stream::{self, StreamExt};
+use log::{debug, info, warn};
 use reqwest::header::{self, HeaderMap};
 use serde_json::json;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::error::TryRecvError;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
 
 mod analytics;
+mod backoff;
+mod cache;
+mod environment_cache;
+mod local;
+mod realtime;
 
+pub mod changes;
+pub mod context;
 pub mod default_handler;
+pub mod evaluation;
 pub mod models;
 pub mod offline_handler;
+pub mod store;
 
 const DEFAULT_API_URL: &str = "https://edge.api.flagsmith.com/api/v1/";
+const DEFAULT_REALTIME_API_URL: &str = "https://realtime.flagsmith.com/";
+const EVENTS_CHANNEL_CAPACITY: usize = 16;
 
 pub struct FlagsmithOptions {
     pub api_url: String,
@@ -33,9 +55,63 @@ pub struct FlagsmithOptions {
     pub enable_local_evaluation: bool,
     pub environment_refresh_interval_mills: u64,
     pub enable_analytics: bool,
+    // Maximum number of distinct features with pending analytics counts, and
+    // what to drop once it is reached (e.g. while the API is unreachable)
+    pub analytics_max_pending_features: usize,
+    pub analytics_overflow_policy: AnalyticsOverflowPolicy,
     pub default_flag_handler: Option<Arc<dyn default_handler::DefaultHandler + Send + Sync>>,
     pub offline_handler: Option<Box<dyn offline_handler::OfflineHandler + Send + Sync>>,
+    // Used like `offline_handler` for environments that are loaded asynchronously
+    // or may fail to load; only one of them can be set
+    pub async_offline_handler: Option<Arc<dyn offline_handler::AsyncOfflineHandler>>,
     pub offline_mode: bool,
+    // Serve flags from `offline_handler`'s environment only while the API is
+    // unavailable instead of always: live data takes precedence over the offline
+    // document, which takes precedence over `default_flag_handler`. Can be combined
+    // with local evaluation, in which case a failed initial fetch does not fail
+    // the client.
+    pub offline_fallback: bool,
+    // With local evaluation, write every fetched environment document to this
+    // path and load it on start, so that flags can be evaluated while the API is
+    // unreachable. Documents last fetched or revalidated more than
+    // `environment_cache_max_age_seconds` ago are ignored. The document's ETag is
+    // kept in `<path>.metadata` so that it is revalidated rather than downloaded
+    // again after a restart.
+    pub environment_cache_path: Option<PathBuf>,
+    pub environment_cache_max_age_seconds: u64,
+    // With local evaluation, write every fetched environment document to this
+    // store. Clients with `environment_store_follower` set read the document from
+    // the store (every `environment_refresh_interval_mills`) instead of fetching it
+    // from the API, so that a single leader polls Flagsmith. Without a store, the
+    // document is only held in memory by the client. A leader only starts from a
+    // stored document fetched less than `environment_store_max_age_seconds` ago.
+    pub environment_store: Option<Arc<dyn EnvironmentStore>>,
+    pub environment_store_follower: bool,
+    pub environment_store_max_age_seconds: u64,
+    // With local evaluation, keep running (and retrying in the background) if
+    // the initial environment fetch fails instead of returning an error.
+    pub allow_degraded_start: bool,
+    // Delay before retrying a failed environment refresh, doubled (with jitter)
+    // on each consecutive failure up to `environment_refresh_max_backoff_mills`,
+    // which is capped at `environment_refresh_interval_mills`.
+    pub environment_refresh_initial_backoff_mills: u64,
+    pub environment_refresh_max_backoff_mills: u64,
+    // With local evaluation, subscribe to the realtime stream and update the
+    // environment as soon as it changes, polling only while disconnected.
+    pub enable_realtime_updates: bool,
+    pub realtime_api_url: String,
+    // Maximum number of identities `get_identities_flags` evaluates at the same
+    // time: concurrent API requests in remote evaluation, blocking threads in
+    // local evaluation (1 evaluates the whole batch on the calling task).
+    pub batch_concurrency: usize,
+    // Without local evaluation, cache the flags returned by the API for
+    // `remote_cache_ttl_mills`, keyed by identifier and traits (environment flags
+    // have their own entry), keeping at most `remote_cache_max_entries` of the
+    // most recently used entries. Note that cached identity requests are not sent,
+    // so their traits are not persisted until the entry expires.
+    pub enable_remote_cache: bool,
+    pub remote_cache_ttl_mills: u64,
+    pub remote_cache_max_entries: usize,
 }
 
 impl Default for FlagsmithOptions {
@@ -46,10 +122,28 @@ impl Default for FlagsmithOptions {
             request_timeout_seconds: 10,
             enable_local_evaluation: false,
             enable_analytics: false,
+            analytics_max_pending_features: 10 * 1000,
+            analytics_overflow_policy: AnalyticsOverflowPolicy::default(),
             environment_refresh_interval_mills: 60 * 1000,
             default_flag_handler: None,
             offline_handler: None,
+            async_offline_handler: None,
             offline_mode: false,
+            offline_fallback: false,
+            environment_cache_path: None,
+            environment_cache_max_age_seconds: 24 * 60 * 60,
+            environment_store: None,
+            environment_store_follower: false,
+            environment_store_max_age_seconds: 24 * 60 * 60,
+            allow_degraded_start: false,
+            environment_refresh_initial_backoff_mills: 1000,
+            environment_refresh_max_backoff_mills: 5 * 60 * 1000,
+            enable_realtime_updates: false,
+            realtime_api_url: DEFAULT_REALTIME_API_URL.to_string(),
+            batch_concurrency: 8,
+            enable_remote_cache: false,
+            remote_cache_ttl_mills: 10 * 1000,
+            remote_cache_max_entries: 10 * 1000,
         }
     }
 }
@@ -60,101 +154,358 @@ pub struct Flagsmith {
     identities_url: String,
     environment_url: String,
     options: FlagsmithOptions,
-    datastore: Arc<Mutex<DataStore>>,
+    datastore: Arc<DataStore>,
     analytics_processor: Option<AnalyticsProcessor>,
-    _polling_thread_tx: Sender<u32>, // to trigger polling manager shutdown
+    flags_cache: Option<FlagsCache>,
+    // Evaluates flags against the environment held locally, or the offline
+    // handler's environment while the API is unavailable (`offline_fallback`)
+    local: LocalEvaluator,
+    refresh_status: Arc<RefreshStatus>,
+    events: broadcast::Sender<EnvironmentEvent>,
+    // Signals the background tasks to stop; also fires when the client is dropped
+    shutdown_tx: watch::Sender<()>,
+    tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
 }
 
+type OfflineEnvironment = Arc<std::sync::RwLock<Option<Arc<Environment>>>>;
+
+// An identity of a batch to evaluate locally: identifier, traits and override
+type BatchItem = (String, Vec<Trait>, Option<Identity>);
+
+// Readers load the current snapshot without taking any lock, so that concurrent
+// evaluations never wait on each other or on a refresh. Refreshes fetch and parse
+// the document beforehand, then swap the new snapshot in atomically.
 struct DataStore {
-    environment: Option<Environment>,
+    snapshot: ArcSwapOption<EnvironmentSnapshot>,
+    environment_metadata: std::sync::Mutex<EnvironmentMetadata>,
+    events: broadcast::Sender<EnvironmentEvent>,
+    environment_cache: Option<Arc<EnvironmentCache>>,
+    environment_store: Option<Arc<dyn EnvironmentStore>>,
+    // Read the environment from `environment_store` instead of the API
+    follower: bool,
+    environment_store_max_age: Duration,
+}
+
+// An environment and the identity overrides it defines, indexed by identifier
+struct EnvironmentSnapshot {
+    environment: Arc<Environment>,
     identities_with_overrides_by_identifier: HashMap<String, Identity>,
 }
 
-impl Flagsmith {
-    pub async fn new(environment_key: String, flagsmith_options: FlagsmithOptions) -> Self {
+impl EnvironmentSnapshot {
+    fn new(environment: Arc<Environment>) -> Self {
+        // Rebuilt from every document so that overrides deleted upstream stop being applied
+        let identities_with_overrides_by_identifier = environment
+            .identity_overrides
+            .iter()
+            .map(|identity| (identity.identifier.clone(), identity.clone()))
+            .collect();
+        EnvironmentSnapshot {
+            environment,
+            identities_with_overrides_by_identifier,
+        }
+    }
+
+    fn identity_override(&self, identifier: &str) -> Option<Identity> {
+        self.identities_with_overrides_by_identifier
+            .get(identifier)
+            .cloned()
+    }
+}
+
+impl DataStore {
+    fn environment(&self) -> Option<Arc<Environment>> {
+        self.snapshot
+            .load()
+            .as_ref()
+            .map(|snapshot| Arc::clone(&snapshot.environment))
+    }
+}
+
+// Tracks the health of environment refreshes so that callers can detect stale flags
+#[derive(Default)]
+struct RefreshStatus {
+    consecutive_failures: AtomicU32,
+    last_success: std::sync::Mutex<Option<DateTime<Utc>>>,
+}
+
+impl RefreshStatus {
+    fn record(&self, result: &Result<(), error::Error>) -> u32 {
+        match result {
+            Ok(_) => {
+                *self.last_success.lock().unwrap() = Some(Utc::now());
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                0
+            }
+            Err(_) => self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1,
+        }
+    }
+}
+
+impl FlagsmithOptions {
+    // Returns an error describing the first invalid combination of options, if any.
+    pub fn validate(&self) -> Result<(), error::Error> {
+        let has_offline_handler =
+            self.offline_handler.is_some() || self.async_offline_handler.is_some();
+        if self.offline_handler.is_some() && self.async_offline_handler.is_some() {
+            return Err(ConfigurationError::MultipleOfflineHandlers.into());
+        }
+        if self.offline_mode && !has_offline_handler {
+            return Err(ConfigurationError::OfflineModeWithoutOfflineHandler.into());
+        }
+        if self.offline_fallback && !has_offline_handler {
+            return Err(ConfigurationError::OfflineFallbackWithoutOfflineHandler.into());
+        }
+        if self.offline_fallback && self.offline_mode {
+            return Err(ConfigurationError::OfflineFallbackWithOfflineMode.into());
+        }
+        let offline_only = has_offline_handler && !self.offline_fallback;
+        if self.default_flag_handler.is_some() && offline_only {
+            return Err(ConfigurationError::DefaultHandlerWithOfflineHandler.into());
+        }
+        if self.enable_local_evaluation && offline_only {
+            return Err(ConfigurationError::LocalEvaluationWithOfflineHandler.into());
+        }
+        if self.enable_realtime_updates && !self.enable_local_evaluation {
+            return Err(ConfigurationError::RealtimeWithoutLocalEvaluation.into());
+        }
+        if self.environment_store.is_some() && !self.enable_local_evaluation {
+            return Err(ConfigurationError::EnvironmentStoreWithoutLocalEvaluation.into());
+        }
+        if self.environment_store_follower && self.environment_store.is_none() {
+            return Err(ConfigurationError::FollowerWithoutEnvironmentStore.into());
+        }
+        if self.environment_store_follower && self.enable_realtime_updates {
+            return Err(ConfigurationError::RealtimeWithFollower.into());
+        }
+        Ok(())
+    }
+}
+
+// Builds a `Flagsmith` client, returning an error instead of panicking on
+// invalid options or a failed initial environment fetch.
+// # Example
+// ```
+// use flagsmith::{Flagsmith, FlagsmithOptions};
+// async fn build() -> Result<Flagsmith, flagsmith::error::Error> {
+//     let options = FlagsmithOptions {
+//         enable_local_evaluation: true,
+//         ..Default::default()
+//     };
+//     Flagsmith::builder("YOUR_ENVIRONMENT_KEY".to_string())
+//         .options(options)
+//         .build()
+//         .await
+// }
+// ```
+pub struct FlagsmithBuilder {
+    environment_key: String,
+    options: FlagsmithOptions,
+}
+
+impl FlagsmithBuilder {
+    pub fn new(environment_key: String) -> Self {
+        FlagsmithBuilder {
+            environment_key,
+            options: FlagsmithOptions::default(),
+        }
+    }
+
+    pub fn options(mut self, options: FlagsmithOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub async fn build(self) -> Result<Flagsmith, error::Error> {
+        let flagsmith_options = self.options;
+        flagsmith_options.validate()?;
+
         let mut headers = flagsmith_options.custom_headers.clone();
         headers.insert(
             "X-Environment-Key",
-            header::HeaderValue::from_str(&environment_key).unwrap(),
+            header::HeaderValue::from_str(&self.environment_key)
+                .map_err(|_| ConfigurationError::InvalidEnvironmentKey)?,
+        );
+        headers.insert(
+            "Content-Type",
+            header::HeaderValue::from_static("application/json"),
         );
-        headers.insert("Content-Type", "application/json".parse().unwrap());
         let timeout = Duration::from_secs(flagsmith_options.request_timeout_seconds);
         let client = reqwest::Client::builder()
             .default_headers(headers.clone())
             .timeout(timeout)
-            .build()
-            .unwrap();
+            .build()?;
 
         let environment_flags_url = format!("{}flags/", flagsmith_options.api_url);
         let identities_url = format!("{}identities/", flagsmith_options.api_url);
         let environment_url = format!("{}environment-document/", flagsmith_options.api_url);
 
-        if flagsmith_options.offline_mode && flagsmith_options.offline_handler.is_none() {
-            panic!("offline_handler must be set to use offline_mode")
-        }
-        if flagsmith_options.default_flag_handler.is_some()
-            && flagsmith_options.offline_handler.is_some()
-        {
-            panic!("default_flag_handler cannot be used with offline_handler")
-        }
-        if flagsmith_options.enable_local_evaluation && flagsmith_options.offline_handler.is_some()
-        {
-            panic!("offline_handler cannot be used with local evaluation")
-        }
-
         // Initialize analytics processor
         let analytics_processor = match flagsmith_options.enable_analytics {
             true => Some(
-                AnalyticsProcessor::new(flagsmith_options.api_url.clone(), headers, timeout, None)
-                    .await,
+                AnalyticsProcessor::new(
+                    flagsmith_options.api_url.clone(),
+                    headers,
+                    timeout,
+                    None,
+                    flagsmith_options.analytics_max_pending_features,
+                    flagsmith_options.analytics_overflow_policy,
+                )
+                .await,
             ),
             false => None,
         };
 
-        // Put the environment model behind mutex to
-        // to share it safely between threads
-        let ds = Arc::new(Mutex::new(DataStore {
-            environment: None,
-            identities_with_overrides_by_identifier: HashMap::new(),
-        }));
-        let (tx, mut rx) = mpsc::channel::<u32>(1);
+        let flags_cache = match flagsmith_options.enable_remote_cache {
+            true => Some(FlagsCache::new(
+                Duration::from_millis(flagsmith_options.remote_cache_ttl_mills),
+                flagsmith_options.remote_cache_max_entries,
+            )),
+            false => None,
+        };
+
+        let (events, _) = broadcast::channel(EVENTS_CHANNEL_CAPACITY);
+        let ds = Arc::new(DataStore {
+            snapshot: ArcSwapOption::empty(),
+            environment_metadata: std::sync::Mutex::new(EnvironmentMetadata::default()),
+            events: events.clone(),
+            environment_cache: flagsmith_options
+                .environment_cache_path
+                .as_ref()
+                .map(|path| {
+                    Arc::new(EnvironmentCache::new(
+                        path.clone(),
+                        Duration::from_secs(flagsmith_options.environment_cache_max_age_seconds),
+                    ))
+                }),
+            environment_store: flagsmith_options.environment_store.clone(),
+            follower: flagsmith_options.environment_store_follower,
+            environment_store_max_age: Duration::from_secs(
+                flagsmith_options.environment_store_max_age_seconds,
+            ),
+        });
+        let (shutdown_tx, _) = watch::channel(());
+        let local = LocalEvaluator {
+            environment_key: self.environment_key.clone(),
+            datastore: Arc::clone(&ds),
+            offline_environment: OfflineEnvironment::default(),
+            analytics_processor: analytics_processor.clone(),
+            default_flag_handler: flagsmith_options.default_flag_handler.clone(),
+        };
 
-        let flagsmith = Flagsmith {
+        let mut flagsmith = Flagsmith {
             client: client.clone(),
             environment_flags_url,
             environment_url: environment_url.clone(),
             identities_url,
-            options: flagsmith_options,
             datastore: Arc::clone(&ds),
             analytics_processor,
-            _polling_thread_tx: tx,
+            flags_cache,
+            options: flagsmith_options,
+            local,
+            refresh_status: Arc::new(RefreshStatus::default()),
+            events,
+            shutdown_tx,
+            tasks: std::sync::Mutex::new(vec![]),
         };
 
-        if flagsmith.options.offline_handler.is_some() {
-            let mut data = flagsmith.datastore.lock().await;
-            data.environment = Some(
+        let offline_handler: Option<Arc<dyn offline_handler::OfflineHandler + Send + Sync>> =
+            flagsmith.options.offline_handler.take().map(Arc::from);
+        let offline_environment = match (&offline_handler, &flagsmith.options.async_offline_handler)
+        {
+            (Some(offline_handler), _) => Some(offline_handler.get_environment()),
+            (None, Some(async_offline_handler)) => {
+                Some(async_offline_handler.get_environment().await?)
+            }
+            (None, None) => None,
+        };
+        if let Some(environment) = offline_environment {
+            let environment = Arc::new(environment);
+            if flagsmith.options.offline_fallback {
+                *flagsmith.local.offline_environment.write().unwrap() = Some(environment);
+            } else {
                 flagsmith
-                    .options
-                    .offline_handler
-                    .as_ref()
-                    .unwrap()
-                    .get_environment(),
-            )
+                    .datastore
+                    .snapshot
+                    .store(Some(Arc::new(EnvironmentSnapshot::new(environment))));
+            }
+        }
+        if let Some(offline_handler) = offline_handler {
+            if let Some(reload_interval) = offline_handler.reload_interval() {
+                let reloader = OfflineReloader {
+                    offline_handler,
+                    datastore: Arc::clone(&ds),
+                    offline_environment: flagsmith
+                        .options
+                        .offline_fallback
+                        .then(|| Arc::clone(&flagsmith.local.offline_environment)),
+                    events: flagsmith.events.clone(),
+                };
+                flagsmith.tasks.lock().unwrap().push(tokio::spawn(
+                    reloader.run(reload_interval, flagsmith.shutdown_tx.subscribe()),
+                ));
+            }
         }
 
         // Create a thread to update environment document
         // If enabled
         let environment_refresh_interval_mills =
             flagsmith.options.environment_refresh_interval_mills;
+        // A failed refresh is never retried later than the next regular refresh
+        let backoff = Backoff::new(
+            Duration::from_millis(flagsmith.options.environment_refresh_initial_backoff_mills),
+            Duration::from_millis(
+                flagsmith
+                    .options
+                    .environment_refresh_max_backoff_mills
+                    .min(environment_refresh_interval_mills),
+            ),
+        );
 
         if flagsmith.options.enable_local_evaluation {
+            // Followers read the store with the initial update below
+            let warm_start = (!ds.follower && load_stored_environment(&ds).await)
+                || load_cached_environment(&ds).await;
             // Update environment once...
-            update_environment(&client, &ds, &environment_url)
-                .await
-                .unwrap();
+            let result = update_environment(&client, &ds, &environment_url).await;
+            let mut failures = flagsmith.refresh_status.record(&result);
+            let mut retry = result.as_ref().is_err_and(|e| e.is_retryable());
+            let mut tasks = flagsmith.tasks.lock().unwrap();
+            if let Err(e) = result {
+                if !flagsmith.options.allow_degraded_start
+                    && !flagsmith.options.offline_fallback
+                    && !warm_start
+                {
+                    return Err(e);
+                }
+                warn!(
+                    "Starting without an environment, initial update failed: {}",
+                    e
+                );
+            }
             // ...and continue updating in the background
+            let realtime_connected = Arc::new(AtomicBool::new(false));
+            if flagsmith.options.enable_realtime_updates {
+                let listener = RealtimeListener {
+                    stream_client: reqwest::Client::builder()
+                        .connect_timeout(timeout)
+                        .build()?,
+                    realtime_api_url: flagsmith.options.realtime_api_url.clone(),
+                    client: client.clone(),
+                    datastore: Arc::clone(&ds),
+                    environment_url: environment_url.clone(),
+                    refresh_status: Arc::clone(&flagsmith.refresh_status),
+                    connected: Arc::clone(&realtime_connected),
+                    backoff,
+                };
+                tasks.push(tokio::spawn(
+                    listener.run(flagsmith.shutdown_tx.subscribe()),
+                ));
+            }
             let ds = Arc::clone(&ds);
-            tokio::spawn(async move {
+            let refresh_status = Arc::clone(&flagsmith.refresh_status);
+            let mut shutdown = flagsmith.shutdown_tx.subscribe();
+            tasks.push(tokio::spawn(async move {
                 let mut interval = tokio::time::interval(Duration::from_millis(
                     environment_refresh_interval_mills,
                 ));
@@ -162,42 +513,101 @@ impl Flagsmith {
                 interval.tick().await;
 
                 loop {
-                    match rx.try_recv() {
-                        Ok(_) | Err(TryRecvError::Disconnected) => {
-                            debug!("shutting down polling manager");
-                            break;
+                    tokio::select! {
+                        _ = shutdown.changed() => break,
+                        // Retry transient failures with backoff instead of waiting for the
+                        // next tick; others (e.g. an invalid key) are only retried on the tick
+                        _ = async {
+                            if !retry {
+                                interval.tick().await;
+                            } else {
+                                tokio::time::sleep(backoff.delay(failures)).await;
+                                interval.reset();
+                            }
+                        } => {}
+                    }
+                    // Stand down while the realtime stream keeps the environment up to
+                    // date, unless the last refresh (e.g. one triggered by an event) failed
+                    if realtime_connected.load(Ordering::Relaxed)
+                        && refresh_status.consecutive_failures.load(Ordering::Relaxed) == 0
+                    {
+                        continue;
+                    }
+                    let result = tokio::select! {
+                        _ = shutdown.changed() => break,
+                        result = update_environment(&client, &ds, &environment_url) => result,
+                    };
+                    let previous_failures = failures;
+                    failures = refresh_status.record(&result);
+                    retry = result.as_ref().is_err_and(|e| e.is_retryable());
+                    match result {
+                        Ok(_) if previous_failures > 0 => {
+                            info!(
+                                "Environment update recovered after {} failed attempts",
+                                previous_failures
+                            );
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!(
+                                "Failed to update environment ({} consecutive failures): {}",
+                                failures, e
+                            );
                         }
-                        Err(TryRecvError::Empty) => {}
                     }
-
-                    interval.tick().await;
-                    update_environment(&client, &ds, &environment_url)
-                        .await
-                        .unwrap();
                 }
-            });
-            // thread::spawn(move || loop {
-            //     match rx.try_recv() {
-            //         Ok(_) | Err(TryRecvError::Disconnected) => {
-            //             debug!("shutting down polling manager");
-            //             break;
-            //         }
-            //         Err(TryRecvError::Empty) => {}
-            //     }
-            //     thread::sleep(Duration::from_millis(environment_refresh_interval_mills));
-            //     update_environment(&client, &ds, &environment_url).unwrap();
-            // });
-        }
-        return flagsmith;
+                debug!("shutting down polling manager");
+            }));
+        }
+        Ok(flagsmith)
+    }
+}
+
+// Background tasks are stopped when the client is dropped, but pending analytics
+// are only flushed on a best-effort basis; call `shutdown` to wait for them.
+impl Drop for Flagsmith {
+    fn drop(&mut self) {
+        let _ = self.shutdown_tx.send(());
+        if let Some(analytics_processor) = &self.analytics_processor {
+            analytics_processor.close();
+        }
+    }
+}
+
+impl Flagsmith {
+    // Panics if the options are invalid or, with local evaluation enabled, if the
+    // initial environment fetch fails. Use `Flagsmith::try_new` or
+    // `Flagsmith::builder` to handle these cases as errors instead.
+    pub async fn new(environment_key: String, flagsmith_options: FlagsmithOptions) -> Self {
+        match Flagsmith::try_new(environment_key, flagsmith_options).await {
+            Ok(flagsmith) => flagsmith,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub async fn try_new(
+        environment_key: String,
+        flagsmith_options: FlagsmithOptions,
+    ) -> Result<Self, error::Error> {
+        FlagsmithBuilder::new(environment_key)
+            .options(flagsmith_options)
+            .build()
+            .await
+    }
+
+    pub fn builder(environment_key: String) -> FlagsmithBuilder {
+        FlagsmithBuilder::new(environment_key)
     }
     //Returns `Flags` struct holding all the flags for the current environment.
     pub async fn get_environment_flags(&self) -> Result<models::Flags, error::Error> {
-        let data = self.datastore.lock().await;
-        if data.environment.is_some() {
-            let environment = data.environment.as_ref().unwrap();
-            return Ok(self.get_environment_flags_from_document(environment));
+        if let Some(environment) = self.datastore.environment() {
+            return Ok(self.local.get_environment_flags_from_document(&environment));
         }
-        return self.default_handler_if_err(self.get_environment_flags_from_api().await);
+        return self
+            .local
+            .fallback_if_err(self.get_environment_flags_from_api().await, |environment| {
+                self.local.get_environment_flags_from_document(environment)
+            });
     }
 
     // Returns all the flags for the current environment for a given identity. Will also
@@ -206,7 +616,7 @@ impl Flagsmith {
     // # Example
     // ```
     // use flagsmith_flag_engine::identities::Trait;
-    // use flagsmith::{Flagsmith, FlagsmithOptions};
+    // use flagsmith::{Flagsmith, FlagsmithOptions, IdentityContext};
     // use flagsmith_flag_engine::types::{FlagsmithValue, FlagsmithValueType};
     // const ENVIRONMENT_KEY: &str = "YOUR_ENVIRONMENT_KEY";
     // fn main(){
@@ -217,280 +627,719 @@ impl Flagsmith {
     //     ];
     //     let mut flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options);
 
-    //     let flags = flagsmith.get_identity_flags("user_identifier".to_string(), traits);
+    //     let context = IdentityContext::new("user_identifier").with_traits(traits);
+    //     let flags = flagsmith.get_identity_flags(context);
     // }
     //```
     pub async fn get_identity_flags(
         &self,
-        identifier: &str,
-        traits: Option<Vec<SDKTrait>>,
-        transient: Option<bool>,
+        context: impl Into<IdentityContext>,
     ) -> Result<Flags, error::Error> {
-        let data = self.datastore.lock().await;
-        let traits = traits.unwrap_or(vec![]);
-        if data.environment.is_some() {
-            let environment = data.environment.as_ref().unwrap();
-            let engine_traits: Vec<Trait> = traits.into_iter().map(|t| t.into()).collect();
-            return self.get_identity_flags_from_document(
-                environment,
-                &data.identities_with_overrides_by_identifier,
-                identifier,
-                engine_traits,
+        let context = context.into();
+        if !self.local.is_own_environment(&context) {
+            // The offline environment is the client's, so only the default handler applies
+            let result = self.get_identity_flags_from_api(&context).await;
+            return self.local.default_if_err(result);
+        }
+        if let Some((environment, identity_override)) =
+            self.local.identity_snapshot(&context.identifier)
+        {
+            return self.local.get_identity_flags_from_document(
+                &environment,
+                identity_override,
+                &context.identifier,
+                context.engine_traits(),
             );
         }
-        return self.default_handler_if_err(
-            self.get_identity_flags_from_api(identifier, traits, transient.unwrap_or(false))
-                .await,
-        );
+        let result = self.get_identity_flags_from_api(&context).await;
+        self.local.fallback_if_err(result, |environment| {
+            self.local.get_offline_identity_flags(environment, &context)
+        })
     }
-    // Returns a list of segments that the given identity is part of
-    pub async fn get_identity_segments(
+
+    // Returns the flags of many identities at once, in the same order as
+    // `identities`, each with its own result. With local evaluation all identities
+    // are evaluated against the same environment snapshot, split across up to
+    // `batch_concurrency` blocking threads. The Flagsmith API has no endpoint
+    // returning the flags of several identities (`bulk-identities` only upserts
+    // traits), so in remote evaluation one request per identity is sent, at most
+    // `batch_concurrency` at a time.
+    pub async fn get_identities_flags<C: Into<IdentityContext>>(
         &self,
-        identifier: &str,
-        traits: Option<Vec<Trait>>,
-    ) -> Result<Vec<Segment>, error::Error> {
-        let data = self.datastore.lock().await;
-        if data.environment.is_none() {
-            return Err(error::Error::new(
-                error::ErrorKind::FlagsmithClientError,
-                "Local evaluation required to obtain identity segments.".to_string(),
-            ));
-        }
-        let environment = data.environment.as_ref().unwrap();
-        let identities_with_overrides_by_identifier = &data.identities_with_overrides_by_identifier;
-        let identity_model = self.get_identity_model(
-            &environment,
-            &identities_with_overrides_by_identifier,
-            identifier,
-            traits.clone().unwrap_or(vec![]),
-        )?;
-        let segments = get_identity_segments(environment, &identity_model, traits.as_ref());
-        return Ok(segments);
+        identities: impl IntoIterator<Item = C>,
+    ) -> Vec<Result<Flags, error::Error>> {
+        let identities: Vec<IdentityContext> = identities.into_iter().map(Into::into).collect();
+        let concurrency = self.options.batch_concurrency.max(1);
+        let all_own_environment = identities
+            .iter()
+            .all(|context| self.local.is_own_environment(context));
+        let snapshot = match all_own_environment {
+            true => self
+                .local
+                .identities_snapshot(identities.iter().map(|context| context.identifier.as_str())),
+            false => None,
+        };
+        if let Some((environment, identity_overrides)) = snapshot {
+            let batch: Vec<_> = identities
+                .into_iter()
+                .zip(identity_overrides)
+                .map(|(context, identity_override)| {
+                    let traits = context.engine_traits();
+                    (context.identifier, traits, identity_override)
+                })
+                .collect();
+            return self
+                .evaluate_identities_flags(environment, batch, concurrency)
+                .await;
+        }
+        stream::iter(identities)
+            .map(|context| self.get_identity_flags(context))
+            .buffered(concurrency)
+            .collect()
+            .await
     }
 
-    fn default_handler_if_err(
+    async fn evaluate_identities_flags(
         &self,
-        result: Result<Flags, error::Error>,
-    ) -> Result<Flags, error::Error> {
-        match result {
-            Ok(result) => Ok(result),
-            Err(e) => {
-                if self.options.default_flag_handler.is_some() {
-                    return Ok(Flags::from_api_flags(
-                        &vec![],
-                        self.analytics_processor.clone(),
-                        self.options.default_flag_handler.clone(),
-                    )
-                    .unwrap());
-                } else {
-                    Err(e)
+        environment: Arc<Environment>,
+        batch: Vec<BatchItem>,
+        concurrency: usize,
+    ) -> Vec<Result<Flags, error::Error>> {
+        let evaluate = {
+            let analytics_processor = self.analytics_processor.clone();
+            let default_flag_handler = self.options.default_flag_handler.clone();
+            move |environment: &Environment, (identifier, traits, identity_override): BatchItem| {
+                evaluate_identity_flags(
+                    environment,
+                    identity_override,
+                    &identifier,
+                    traits,
+                    analytics_processor.clone(),
+                    default_flag_handler.clone(),
+                )
+            }
+        };
+        if concurrency == 1 || batch.len() < 2 {
+            return batch
+                .into_iter()
+                .map(|item| Ok(evaluate(&environment, item)))
+                .collect();
+        }
+        let chunk_size = batch.len().div_ceil(concurrency);
+        let mut batch = batch.into_iter();
+        let mut chunks = vec![];
+        loop {
+            let chunk: Vec<_> = batch.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk_len = chunk.len();
+            let environment = Arc::clone(&environment);
+            let evaluate = evaluate.clone();
+            let handle = tokio::task::spawn_blocking(move || {
+                chunk
+                    .into_iter()
+                    .map(|item| evaluate(&environment, item))
+                    .collect::<Vec<_>>()
+            });
+            chunks.push((chunk_len, handle));
+        }
+        let mut results = vec![];
+        for (chunk_len, handle) in chunks {
+            match handle.await {
+                Ok(chunk_flags) => results.extend(chunk_flags.into_iter().map(Ok)),
+                Err(e) => {
+                    let message = format!("Identity evaluation failed: {}", e);
+                    results
+                        .extend((0..chunk_len).map(|_| Err(error::Error::Client(message.clone()))));
                 }
             }
         }
+        results
     }
-    fn get_environment_flags_from_document(&self, environment: &Environment) -> models::Flags {
-        return models::Flags::from_feature_states(
-            &environment.feature_states,
-            self.analytics_processor.clone(),
-            self.options.default_flag_handler.clone(),
-            None,
-        );
+
+    // Returns a list of segments that the given identity is part of
+    pub async fn get_identity_segments(
+        &self,
+        context: impl Into<IdentityContext>,
+    ) -> Result<Vec<Segment>, error::Error> {
+        self.local.identity_segments(context)
     }
-    pub async fn update_environment(&mut self) -> Result<(), error::Error> {
-        return update_environment(&self.client, &self.datastore, &self.environment_url).await;
+
+    // Returns a cheap, cloneable handle evaluating flags synchronously against the
+    // latest environment held locally, e.g. for code paths that cannot await.
+    // Evaluators must not outlive the client: once it is dropped or shut down the
+    // environment is no longer refreshed and their evaluations are not reported
+    // to analytics.
+    pub fn local(&self) -> LocalEvaluator {
+        self.local.clone()
     }
 
-    fn get_identity_flags_from_document(
+    // Returns every decision taken to evaluate `feature_name` for the given identity
+    // (environment value, segment and identity overrides, multivariate split), for
+    // debugging why an identity gets a flag. Requires the environment to be held
+    // locally.
+    pub async fn explain_identity_flag(
         &self,
-        environment: &Environment,
-        identities_with_overrides_by_identifier: &HashMap<String, Identity>,
-        identifier: &str,
-        traits: Vec<Trait>,
-    ) -> Result<Flags, error::Error> {
-        let identity = self.get_identity_model(
-            environment,
-            identities_with_overrides_by_identifier,
-            identifier,
+        context: impl Into<IdentityContext>,
+        feature_name: &str,
+    ) -> Result<EvaluationTrace, error::Error> {
+        let context = context.into();
+        let (environment, identity_override) = self.local.own_identity_snapshot(&context)?;
+        let traits = context.engine_traits();
+        let identity_model = get_identity_model(
+            &environment,
+            identity_override,
+            &context.identifier,
             traits.clone(),
-        )?;
-        let feature_states =
-            engine::get_identity_feature_states(environment, &identity, Some(traits.as_ref()));
-        let flags = Flags::from_feature_states(
-            &feature_states,
-            self.analytics_processor.clone(),
-            self.options.default_flag_handler.clone(),
-            Some(&identity.composite_key()),
         );
-        return Ok(flags);
+        let mut trace = evaluation::explain_identity_flag(
+            &environment,
+            &identity_model,
+            Some(&traits),
+            feature_name,
+        );
+        if trace.flag.is_none() {
+            if let Some(handler) = &self.options.default_flag_handler {
+                let mut flag = handler.get_default(feature_name);
+                flag.reason = models::EvaluationReason::HandlerDefault;
+                trace.steps.push(evaluation::EvaluationStep::HandlerDefault);
+                trace.flag = Some(flag);
+            }
+        }
+        Ok(trace)
     }
 
-    fn get_identity_model(
-        &self,
-        environment: &Environment,
-        identities_with_overrides_by_identifier: &HashMap<String, Identity>,
-        identifier: &str,
-        traits: Vec<Trait>,
-    ) -> Result<Identity, error::Error> {
-        let mut identity: Identity;
-
-        if identities_with_overrides_by_identifier.contains_key(identifier) {
-            identity = identities_with_overrides_by_identifier
-                .get(identifier)
-                .unwrap()
-                .clone();
-        } else {
-            identity = Identity::new(identifier.to_string(), environment.api_key.clone());
+    pub async fn update_environment(&mut self) -> Result<(), error::Error> {
+        let result = update_environment(&self.client, &self.datastore, &self.environment_url).await;
+        self.refresh_status.record(&result);
+        result
+    }
+
+    // Returns a receiver of events emitted whenever the environment is refreshed,
+    // including which environment flags changed. Events are only emitted when the
+    // environment is held locally (local evaluation or offline handler).
+    pub fn subscribe(&self) -> broadcast::Receiver<EnvironmentEvent> {
+        self.events.subscribe()
+    }
+
+    // Returns a receiver holding the current environment flag for `feature_name`
+    // (or `None` if it does not exist) that is updated whenever a refresh changes it.
+    pub fn watch_flag(&self, feature_name: &str) -> watch::Receiver<Option<models::Flag>> {
+        let mut events = self.events.subscribe();
+        let datastore = Arc::clone(&self.datastore);
+        let feature_name = feature_name.to_string();
+        let current_flag = |data: &DataStore, feature_name: &str| {
+            data.environment()
+                .and_then(|environment| changes::environment_flag(&environment, feature_name))
+        };
+        let (tx, rx) = watch::channel(current_flag(&datastore, &feature_name));
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = tx.closed() => break,
+                    event = events.recv() => event,
+                };
+                match event {
+                    Ok(EnvironmentEvent::Updated(change)) => {
+                        if let Some(flag_change) = change.get(&feature_name) {
+                            tx.send_replace(flag_change.current.clone());
+                        }
+                    }
+                    Ok(EnvironmentEvent::RefreshFailed { .. }) => {}
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        tx.send_replace(current_flag(&datastore, &feature_name));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        rx
+    }
+
+    // Stops polling and realtime updates immediately, performs a final analytics
+    // flush and waits for the background tasks to finish. Returns an error if the
+    // final flush failed. The client keeps serving the last known flags afterwards.
+    pub async fn shutdown(&self) -> Result<(), error::Error> {
+        let _ = self.shutdown_tx.send(());
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(e) = task.await {
+                warn!("Background task failed during shutdown: {}", e);
+            }
         }
+        match &self.analytics_processor {
+            Some(analytics_processor) => analytics_processor.shutdown().await,
+            None => Ok(()),
+        }
+    }
 
-        identity.identity_traits = traits;
-        return Ok(identity.to_owned());
+    // Returns the analytics delivery counters, or `None` if analytics are disabled
+    pub fn analytics_stats(&self) -> Option<AnalyticsStats> {
+        self.analytics_processor
+            .as_ref()
+            .map(|analytics_processor| analytics_processor.stats())
     }
+
+    // Returns the number of environment updates that have failed since the last successful one
+    pub fn consecutive_refresh_failures(&self) -> u32 {
+        self.refresh_status
+            .consecutive_failures
+            .load(Ordering::Relaxed)
+    }
+
+    // Returns when the environment was last updated successfully, if ever
+    pub fn last_successful_refresh(&self) -> Option<DateTime<Utc>> {
+        *self.refresh_status.last_success.lock().unwrap()
+    }
+
     async fn get_identity_flags_from_api(
         &self,
-        identifier: &str,
-        traits: Vec<SDKTrait>,
-        transient: bool,
+        context: &IdentityContext,
+    ) -> Result<Flags, error::Error> {
+        match &self.flags_cache {
+            Some(cache) => {
+                cache
+                    .get_or_fetch(CacheKey::identity(context), || {
+                        self.request_identity_flags(context)
+                    })
+                    .await
+            }
+            None => self.request_identity_flags(context).await,
+        }
+    }
+    async fn request_identity_flags(
+        &self,
+        context: &IdentityContext,
     ) -> Result<Flags, error::Error> {
         let method = reqwest::Method::POST;
 
-        let json = json!({"identifier":identifier, "traits": traits, "transient": transient});
-        let response = get_json_response(
-            &self.client,
-            method,
-            self.identities_url.clone(),
-            Some(json.to_string()),
-        )
-        .await?;
+        let json = json!({
+            "identifier": context.identifier,
+            "traits": context.traits,
+            "transient": context.transient
+        });
+        let mut request = self
+            .client
+            .request(method, self.identities_url.clone())
+            .body(json.to_string());
+        if let Some(environment_key) = &context.environment_key {
+            request = request.header("X-Environment-Key", environment_key);
+        }
+        let response = get_json_response(request).await?;
         // Cast to array of values
-        let api_flags = response["flags"].as_array().ok_or(error::Error::new(
-            error::ErrorKind::FlagsmithAPIError,
-            "Unable to get valid response from Flagsmith API.".to_string(),
-        ))?;
+        let api_flags = response["flags"].as_array().ok_or_else(|| {
+            error::Error::decode("Unable to get valid response from Flagsmith API.")
+        })?;
 
         let flags = Flags::from_api_flags(
             api_flags,
             self.analytics_processor.clone(),
             self.options.default_flag_handler.clone(),
         )
-        .ok_or(error::Error::new(
-            error::ErrorKind::FlagsmithAPIError,
-            "Unable to get valid response from Flagsmith API.".to_string(),
-        ))?;
-        return Ok(flags);
+        .ok_or_else(|| error::Error::decode("Unable to get valid response from Flagsmith API."))?;
+        Ok(flags)
     }
     async fn get_environment_flags_from_api(&self) -> Result<Flags, error::Error> {
+        match &self.flags_cache {
+            Some(cache) => {
+                cache
+                    .get_or_fetch(CacheKey::Environment, || self.request_environment_flags())
+                    .await
+            }
+            None => self.request_environment_flags().await,
+        }
+    }
+    async fn request_environment_flags(&self) -> Result<Flags, error::Error> {
         let method = reqwest::Method::GET;
-        let api_flags = get_json_response(
-            &self.client,
-            method,
-            self.environment_flags_url.clone(),
-            None,
-        )
-        .await?;
+        let request = self
+            .client
+            .request(method, self.environment_flags_url.clone());
+        let api_flags = get_json_response(request).await?;
         // Cast to array of values
-        let api_flags = api_flags.as_array().ok_or(error::Error::new(
-            error::ErrorKind::FlagsmithAPIError,
-            "Unable to get valid response from Flagsmith API.".to_string(),
-        ))?;
+        let api_flags = api_flags.as_array().ok_or_else(|| {
+            error::Error::decode("Unable to get valid response from Flagsmith API.")
+        })?;
 
         let flags = Flags::from_api_flags(
             api_flags,
             self.analytics_processor.clone(),
             self.options.default_flag_handler.clone(),
         )
-        .ok_or(error::Error::new(
-            error::ErrorKind::FlagsmithAPIError,
-            "Unable to get valid response from Flagsmith API.".to_string(),
-        ))?;
-        return Ok(flags);
+        .ok_or_else(|| error::Error::decode("Unable to get valid response from Flagsmith API."))?;
+        Ok(flags)
     }
 }
 
-async fn get_environment_from_api(
+// Returns `None` if the server reports that the document described by
+// `metadata` has not been modified.
+async fn get_environment_document_from_api(
     client: &reqwest::Client,
-    environment_url: String,
-) -> Result<Environment, error::Error> {
-    let method = reqwest::Method::GET;
-    let json_document = get_json_response(client, method, environment_url, None).await?;
-    let environment = build_environment_struct(json_document);
-    return Ok(environment);
+    environment_url: &str,
+    metadata: &EnvironmentMetadata,
+) -> Result<Option<(serde_json::Value, EnvironmentMetadata)>, error::Error> {
+    let mut request = client.get(environment_url);
+    if let Some(etag) = &metadata.etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &metadata.last_modified {
+        request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(error::Error::Http {
+            status: response.status(),
+            body: response.text().await?,
+        });
+    }
+    let header_value = |name: header::HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let etag = header_value(header::ETAG);
+    let last_modified = header_value(header::LAST_MODIFIED);
+    let document: serde_json::Value = response.json().await?;
+    let updated_at = document["updated_at"]
+        .as_str()
+        .map(|value| value.to_string());
+    Ok(Some((
+        document,
+        EnvironmentMetadata {
+            etag,
+            last_modified,
+            updated_at,
+        },
+    )))
+}
+
+fn get_identity_model(
+    environment: &Environment,
+    identity_override: Option<Identity>,
+    identifier: &str,
+    traits: Vec<Trait>,
+) -> Identity {
+    let mut identity = identity_override
+        .unwrap_or_else(|| Identity::new(identifier.to_string(), environment.api_key.clone()));
+
+    identity.identity_traits = traits;
+    identity
+}
+
+// Evaluates the flags of an identity against `environment`. Does not need the
+// client, so that batches can be evaluated on blocking threads.
+fn evaluate_identity_flags(
+    environment: &Environment,
+    identity_override: Option<Identity>,
+    identifier: &str,
+    traits: Vec<Trait>,
+    analytics_processor: Option<AnalyticsProcessor>,
+    default_flag_handler: Option<Arc<dyn default_handler::DefaultHandler + Send + Sync>>,
+) -> Flags {
+    let identity = get_identity_model(environment, identity_override, identifier, traits.clone());
+    Flags::from_flags(
+        evaluation::identity_flags(environment, &identity, Some(&traits)),
+        analytics_processor,
+        default_flag_handler,
+    )
 }
 
 async fn update_environment(
     client: &reqwest::Client,
-    datastore: &Arc<Mutex<DataStore>>,
-    environment_url: &String,
+    datastore: &DataStore,
+    environment_url: &str,
 ) -> Result<(), error::Error> {
-    println!("Updating env");
-    let mut data = datastore.lock().await;
-    let environment = Some(get_environment_from_api(&client, environment_url.clone()).await?);
-    for identity in &environment.as_ref().unwrap().identity_overrides {
-        data.identities_with_overrides_by_identifier
-            .insert(identity.identifier.clone(), identity.clone());
-    }
-    data.environment = environment;
-    return Ok(());
+    let result = match &datastore.environment_store {
+        Some(environment_store) if datastore.follower => {
+            sync_environment_from_store(datastore, environment_store.as_ref()).await
+        }
+        _ => fetch_and_store_environment(client, datastore, environment_url).await,
+    };
+    if let Err(e) = &result {
+        let _ = datastore.events.send(EnvironmentEvent::RefreshFailed {
+            message: e.to_string(),
+        });
+    }
+    result
 }
 
-async fn get_json_response(
+async fn fetch_and_store_environment(
     client: &reqwest::Client,
-    method: reqwest::Method,
-    url: String,
-    body: Option<String>,
-) -> Result<serde_json::Value, error::Error> {
-    let mut request = client.request(method, url);
-    if body.is_some() {
-        request = request.body(body.unwrap());
+    datastore: &DataStore,
+    environment_url: &str,
+) -> Result<(), error::Error> {
+    debug!("Updating environment");
+    let metadata = datastore.environment_metadata.lock().unwrap().clone();
+    let environment_cache = &datastore.environment_cache;
+    let environment_store = &datastore.environment_store;
+    // Fetch and parse before taking the lock so that readers are never blocked on the network
+    let (document, metadata) =
+        match get_environment_document_from_api(client, environment_url, &metadata).await? {
+            Some(response) => response,
+            None => {
+                debug!("Environment document not modified");
+                touch_environment_cache(datastore).await;
+                return Ok(());
+            }
+        };
+    let cache_entry = match environment_cache {
+        Some(_) => Some((serde_json::to_vec(&document)?, metadata.clone())),
+        None => None,
     };
-    let response = request.send().await?;
-    if response.status().is_success() {
-        return Ok(response.json().await?);
-    } else {
-        return Err(error::Error::new(
-            error::ErrorKind::FlagsmithAPIError,
-            response.text().await?,
-        ));
+    let stored_environment = environment_store.as_ref().map(|_| StoredEnvironment {
+        document: document.clone(),
+        metadata: metadata.clone(),
+        stored_at: Utc::now().timestamp() as u64,
+    });
+    store_environment(datastore, document, metadata)?;
+    if let (Some(environment_cache), Some((contents, metadata))) = (environment_cache, cache_entry)
+    {
+        if let Err(e) = environment_cache.store(&contents, &metadata).await {
+            warn!("Failed to write the environment cache: {}", e);
+        }
+    }
+    if let (Some(environment_store), Some(stored_environment)) =
+        (environment_store, stored_environment)
+    {
+        if let Err(e) = environment_store.put(stored_environment).await {
+            warn!("Failed to write the environment to the store: {}", e);
+        }
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use httpmock::prelude::*;
-    use tokio::time::sleep;
+async fn touch_environment_cache(datastore: &DataStore) {
+    if let Some(environment_cache) = &datastore.environment_cache {
+        if let Err(e) = environment_cache.touch().await {
+            warn!("Failed to refresh the environment cache: {}", e);
+        }
+    }
+}
 
-    static ENVIRONMENT_JSON: &str = r#"{
-        "api_key": "B62qaMZNwfiqT76p38ggrQ",
-        "project": {
-          "name": "Test project",
-          "organisation": {
-            "feature_analytics": false,
-            "name": "Test Org",
-            "id": 1,
-            "persist_trait_data": true,
-            "stop_serving_flags": false
-          },
-          "segments": [],
-          "id": 1,
-          "hide_disabled_flags": false
-        },
-        "segment_overrides": [],
-        "id": 1,
-        "feature_states": [
-          {
-            "multivariate_feature_state_values": [],
-            "feature_state_value": "some-value",
-            "id": 1,
-            "featurestate_uuid": "40eb539d-3713-4720-bbd4-829dbef10d51",
-            "feature": {
-              "name": "some_feature",
-              "type": "STANDARD",
-              "id": 1
-            },
-            "segment_id": null,
-            "enabled": true
-          },
-          {
+// Reads the environment written to the store by the leader, if it changed
+async fn sync_environment_from_store(
+    datastore: &DataStore,
+    environment_store: &dyn EnvironmentStore,
+) -> Result<(), error::Error> {
+    debug!("Reading environment from the store");
+    let stored_environment = environment_store
+        .get()
+        .await?
+        .ok_or(error::Error::EmptyEnvironmentStore)?;
+    // The document may change without its `updated_at`, e.g. when only identity
+    // overrides are edited, so only an unchanged ETag means it can be skipped
+    let current_etag = datastore.environment_metadata.lock().unwrap().etag.clone();
+    if current_etag.is_some() && current_etag == stored_environment.metadata.etag {
+        debug!("Stored environment unchanged since last update");
+        return Ok(());
+    }
+    store_environment(
+        datastore,
+        stored_environment.document,
+        stored_environment.metadata,
+    )
+}
+
+// Parses the document, then swaps it in
+fn store_environment(
+    datastore: &DataStore,
+    document: serde_json::Value,
+    metadata: EnvironmentMetadata,
+) -> Result<(), error::Error> {
+    let environment: Environment = serde_json::from_value(document)?;
+    swap_environment(datastore, Arc::new(environment), metadata);
+    Ok(())
+}
+
+// Swaps the environment in and notifies subscribers of the flags that changed
+fn swap_environment(
+    datastore: &DataStore,
+    environment: Arc<Environment>,
+    metadata: EnvironmentMetadata,
+) {
+    let snapshot = Arc::new(EnvironmentSnapshot::new(environment));
+    *datastore.environment_metadata.lock().unwrap() = metadata;
+    let previous = datastore.snapshot.swap(Some(Arc::clone(&snapshot)));
+    if datastore.events.receiver_count() > 0 {
+        let change = EnvironmentChange::between(
+            previous
+                .as_ref()
+                .map(|previous| previous.environment.as_ref()),
+            &snapshot.environment,
+        );
+        let _ = datastore.events.send(EnvironmentEvent::Updated(change));
+    }
+}
+
+// Periodically reloads the offline handler's environment, swapping it into the
+// datastore, or into the offline environment with `offline_fallback`
+struct OfflineReloader {
+    offline_handler: Arc<dyn offline_handler::OfflineHandler + Send + Sync>,
+    datastore: Arc<DataStore>,
+    offline_environment: Option<OfflineEnvironment>,
+    events: broadcast::Sender<EnvironmentEvent>,
+}
+
+impl OfflineReloader {
+    async fn run(self, reload_interval: Duration, mut shutdown: watch::Receiver<()>) {
+        let mut interval = tokio::time::interval(reload_interval);
+        // The environment was just read by the client
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                _ = interval.tick() => {}
+            }
+            let offline_handler = Arc::clone(&self.offline_handler);
+            let result = match tokio::task::spawn_blocking(move || offline_handler.reload()).await {
+                Ok(result) => result,
+                Err(e) => Err(error::Error::Client(e.to_string())),
+            };
+            match result {
+                Ok(Some(environment)) => {
+                    info!("Reloaded the offline environment");
+                    let environment = Arc::new(environment);
+                    match &self.offline_environment {
+                        Some(offline_environment) => {
+                            *offline_environment.write().unwrap() = Some(environment);
+                        }
+                        None => {
+                            swap_environment(
+                                &self.datastore,
+                                environment,
+                                EnvironmentMetadata::default(),
+                            );
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Failed to reload the offline environment: {}", e);
+                    let _ = self.events.send(EnvironmentEvent::RefreshFailed {
+                        message: e.to_string(),
+                    });
+                }
+            }
+        }
+        debug!("shutting down offline environment reloader");
+    }
+}
+
+// Loads the document written by the environment cache, if any, returning whether
+// the datastore now holds an environment
+async fn load_cached_environment(datastore: &DataStore) -> bool {
+    let (document, metadata) = match &datastore.environment_cache {
+        Some(environment_cache) => match environment_cache.load().await {
+            Some(entry) => entry,
+            None => return false,
+        },
+        None => return false,
+    };
+    match store_environment(datastore, document, metadata) {
+        Ok(_) => {
+            info!("Loaded the environment from the environment cache");
+            true
+        }
+        Err(e) => {
+            warn!("Ignoring invalid cached environment: {}", e);
+            false
+        }
+    }
+}
+
+// Loads the environment from the environment store, if any and recent enough,
+// returning whether the datastore now holds an environment
+async fn load_stored_environment(datastore: &DataStore) -> bool {
+    let Some(environment_store) = &datastore.environment_store else {
+        return false;
+    };
+    let stored_environment = match environment_store.get().await {
+        Ok(Some(stored_environment)) => stored_environment,
+        Ok(None) => return false,
+        Err(e) => {
+            warn!("Unable to load the environment from the store: {}", e);
+            return false;
+        }
+    };
+    let age = (Utc::now().timestamp() as u64).saturating_sub(stored_environment.stored_at);
+    if age > datastore.environment_store_max_age.as_secs() {
+        debug!("Ignoring stored environment, fetched {}s ago", age);
+        return false;
+    }
+    match store_environment(
+        datastore,
+        stored_environment.document,
+        stored_environment.metadata,
+    ) {
+        Ok(_) => {
+            info!("Loaded the environment from the environment store");
+            true
+        }
+        Err(e) => {
+            warn!("Ignoring invalid stored environment: {}", e);
+            false
+        }
+    }
+}
+
+async fn get_json_response(
+    request: reqwest::RequestBuilder,
+) -> Result<serde_json::Value, error::Error> {
+    let response = request.send().await?;
+    if response.status().is_success() {
+        Ok(response.json().await?)
+    } else {
+        Err(error::Error::Http {
+            status: response.status(),
+            body: response.text().await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::prelude::*;
+    use models::SDKTrait;
+    use tokio::time::sleep;
+
+    static ENVIRONMENT_JSON: &str = r#"{
+        "api_key": "B62qaMZNwfiqT76p38ggrQ",
+        "project": {
+          "name": "Test project",
+          "organisation": {
+            "feature_analytics": false,
+            "name": "Test Org",
+            "id": 1,
+            "persist_trait_data": true,
+            "stop_serving_flags": false
+          },
+          "segments": [],
+          "id": 1,
+          "hide_disabled_flags": false
+        },
+        "segment_overrides": [],
+        "id": 1,
+        "feature_states": [
+          {
+            "multivariate_feature_state_values": [],
+            "feature_state_value": "some-value",
+            "id": 1,
+            "featurestate_uuid": "40eb539d-3713-4720-bbd4-829dbef10d51",
+            "feature": {
+              "name": "some_feature",
+              "type": "STANDARD",
+              "id": 1
+            },
+            "segment_id": null,
+            "enabled": true
+          },
+          {
             "feature": {
               "id": 83755,
               "name": "test_mv",
@@ -580,6 +1429,50 @@ mod tests {
         api_mock.assert();
     }
 
+    #[tokio::test]
+    async fn shutdown_stops_polling_and_flushes_analytics() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+
+        let mock_server = MockServer::start();
+        let api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let analytics_mock = mock_server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/analytics/flags/")
+                .json_body(json!({"some_feature": 1}));
+            then.status(200);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            environment_refresh_interval_mills: 60 * 60 * 1000,
+            enable_local_evaluation: true,
+            enable_analytics: true,
+            ..Default::default()
+        };
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        flagsmith
+            .get_environment_flags()
+            .await
+            .unwrap()
+            .get_flag("some_feature")
+            .unwrap();
+
+        // When
+        let result = tokio::time::timeout(Duration::from_secs(5), flagsmith.shutdown()).await;
+
+        // Then
+        assert!(result.unwrap().is_ok());
+        analytics_mock.assert();
+        assert_eq!(flagsmith.analytics_stats().unwrap().successful_flushes, 1);
+        assert!(flagsmith.tasks.lock().unwrap().is_empty());
+        sleep(Duration::from_millis(50)).await;
+        api_mock.assert_hits(1);
+    }
+
     #[tokio::test]
     async fn polling_thread_updates_environment_on_each_refresh() {
         // Given
@@ -612,51 +1505,1327 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_local_evaluation_identity_override_evaluate_expected() {
+    async fn polling_thread_backoff_is_capped_at_the_refresh_interval() {
+        // Given an API that keeps failing and the default maximum backoff
+        let environment_key = "ser.test_environment_key";
+        let mock_server = MockServer::start();
+        let api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(500);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            environment_refresh_interval_mills: 100,
+            environment_refresh_initial_backoff_mills: 50,
+            enable_local_evaluation: true,
+            allow_degraded_start: true,
+            ..Default::default()
+        };
+
+        // When
+        let _flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        sleep(std::time::Duration::from_millis(1000)).await;
+
+        // Then retries keep coming at least once per interval
+        assert!(api_mock.hits() >= 8);
+    }
+
+    #[tokio::test]
+    async fn polling_thread_does_not_back_off_on_non_retryable_failures() {
+        // Given an environment key the API rejects
+        let environment_key = "ser.test_environment_key";
+        let mock_server = MockServer::start();
+        let api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(401);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            environment_refresh_interval_mills: 60 * 60 * 1000,
+            environment_refresh_initial_backoff_mills: 10,
+            environment_refresh_max_backoff_mills: 10,
+            enable_local_evaluation: true,
+            allow_degraded_start: true,
+            ..Default::default()
+        };
+
+        // When
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        sleep(std::time::Duration::from_millis(100)).await;
+
+        // Then the request is only repeated on the regular interval
+        api_mock.assert_hits(1);
+        assert_eq!(flagsmith.consecutive_refresh_failures(), 1);
+    }
+
+    #[tokio::test]
+    async fn polling_thread_survives_failed_updates_and_reports_staleness() {
         // Given
         let environment_key = "ser.test_environment_key";
         let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
 
         let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body.clone());
+        });
+
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            environment_refresh_interval_mills: 50,
+            environment_refresh_initial_backoff_mills: 20,
+            environment_refresh_max_backoff_mills: 40,
+            enable_local_evaluation: true,
+            ..Default::default()
+        };
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        let first_refresh = flagsmith.last_successful_refresh().unwrap();
+        assert_eq!(flagsmith.consecutive_refresh_failures(), 0);
+
+        // When the API starts failing
+        api_mock.delete();
+        let mut failing_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(500);
+        });
+        sleep(std::time::Duration::from_millis(200)).await;
+
+        // Then the polling thread keeps retrying and reports the failures
+        assert!(failing_mock.hits() >= 2);
+        assert!(flagsmith.consecutive_refresh_failures() >= 2);
+        let last_refresh = flagsmith.last_successful_refresh().unwrap();
+        // And the environment fetched before the failures is still served
+        let flags = flagsmith.get_environment_flags().await.unwrap();
+        assert_eq!(
+            flags.get_feature_value_as_string("some_feature").unwrap(),
+            "some-value"
+        );
+
+        // When the API recovers
+        failing_mock.delete();
         mock_server.mock(|when, then| {
-            when.method(GET)
-                .path("/api/v1/environment-document/")
-                .header("X-Environment-Key", environment_key);
+            when.method(GET).path("/api/v1/environment-document/");
             then.status(200).json_body(response_body);
         });
+        sleep(std::time::Duration::from_millis(150)).await;
 
-        let url = mock_server.url("/api/v1/");
+        // Then
+        assert_eq!(flagsmith.consecutive_refresh_failures(), 0);
+        assert!(flagsmith.last_successful_refresh().unwrap() > last_refresh);
+        assert!(last_refresh >= first_refresh);
+    }
+
+    #[tokio::test]
+    async fn reads_are_not_blocked_by_an_in_flight_environment_update() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
 
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body.clone());
+        });
         let flagsmith_options = FlagsmithOptions {
-            api_url: url,
-            environment_refresh_interval_mills: 100,
+            api_url: mock_server.url("/api/v1/"),
+            environment_refresh_interval_mills: 50,
             enable_local_evaluation: true,
             ..Default::default()
         };
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
 
-        // When
-        let mut _flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        // When the next refresh is slow to respond
+        api_mock.delete();
+        let slow_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200)
+                .json_body(response_body)
+                .delay(std::time::Duration::from_millis(1000));
+        });
+        sleep(std::time::Duration::from_millis(100)).await;
+        let started = std::time::Instant::now();
+        let flags = flagsmith.get_environment_flags().await.unwrap();
+        let identity_flags = flagsmith.get_identity_flags("overridden-id").await.unwrap();
 
-        // Then
-        let flags = _flagsmith.get_environment_flags();
-        let identity_flags = _flagsmith.get_identity_flags("overridden-id", None, None);
+        // Then reads are served from the current environment without waiting for it
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+        assert!(slow_mock.hits() >= 1);
         assert_eq!(
-            flags
-                .await
-                .unwrap()
-                .get_feature_value_as_string("some_feature")
-                .unwrap()
-                .to_owned(),
+            flags.get_feature_value_as_string("some_feature").unwrap(),
             "some-value"
         );
         assert_eq!(
             identity_flags
-                .await
-                .unwrap()
                 .get_feature_value_as_string("some_feature")
-                .unwrap()
-                .to_owned(),
+                .unwrap(),
             "some-overridden-value"
         );
     }
+
+    #[tokio::test]
+    async fn update_environment_sends_conditional_request_and_skips_unmodified_document() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let etag = "\"v1\"";
+        let last_modified = "Fri, 14 Jul 2023 16:12:00 GMT";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+
+        let mock_server = MockServer::start();
+        let unconditional_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/environment-document/")
+                .matches(|request| {
+                    !request.headers.iter().flatten().any(|(name, _)| {
+                        name.eq_ignore_ascii_case("if-none-match")
+                            || name.eq_ignore_ascii_case("if-modified-since")
+                    })
+                });
+            then.status(200)
+                .header("ETag", etag)
+                .header("Last-Modified", last_modified)
+                .json_body(response_body);
+        });
+        let conditional_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/environment-document/")
+                .header("If-None-Match", etag)
+                .header("If-Modified-Since", last_modified);
+            then.status(304);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            ..Default::default()
+        };
+        let mut flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        let environment = flagsmith.datastore.environment().unwrap();
+
+        // When
+        flagsmith.update_environment().await.unwrap();
+
+        // Then
+        unconditional_mock.assert();
+        conditional_mock.assert();
+        assert!(Arc::ptr_eq(
+            &environment,
+            &flagsmith.datastore.environment().unwrap()
+        ));
+        assert_eq!(
+            flagsmith
+                .datastore
+                .environment_metadata
+                .lock()
+                .unwrap()
+                .etag
+                .as_deref(),
+            Some(etag)
+        );
+    }
+
+    #[tokio::test]
+    async fn update_environment_rebuilds_every_document_returned_with_200() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+
+        let mock_server = MockServer::start();
+        let api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            ..Default::default()
+        };
+        let mut flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        let environment = flagsmith.datastore.environment().unwrap();
+
+        // When
+        flagsmith.update_environment().await.unwrap();
+
+        // Then the document is used even though its `updated_at` did not change
+        api_mock.assert_hits(2);
+        assert!(!Arc::ptr_eq(
+            &environment,
+            &flagsmith.datastore.environment().unwrap()
+        ));
+    }
+
+    // Serves a single `environment_updated` event and keeps the stream open
+    // until `close` is notified.
+    async fn start_realtime_stand_in(
+        updated_at: f64,
+        close: Arc<tokio::sync::Notify>,
+    ) -> (String, Arc<AtomicU32>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&connections);
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::Relaxed);
+                let mut request = vec![0; 4096];
+                let _ = socket.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n\
+                     event: environment_updated\ndata: {{\"updated_at\": {}}}\n\n",
+                    updated_at
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                close.notified().await;
+            }
+        });
+        (url, connections)
+    }
+
+    #[tokio::test]
+    async fn realtime_event_triggers_update_and_pauses_polling_while_connected() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+
+        let mock_server = MockServer::start();
+        let api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let close = Arc::new(tokio::sync::Notify::new());
+        let (realtime_api_url, connections) =
+            start_realtime_stand_in(Utc::now().timestamp() as f64, Arc::clone(&close)).await;
+
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            environment_refresh_interval_mills: 50,
+            environment_refresh_initial_backoff_mills: 1000,
+            enable_local_evaluation: true,
+            enable_realtime_updates: true,
+            realtime_api_url,
+            ..Default::default()
+        };
+
+        // When
+        let _flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        sleep(std::time::Duration::from_millis(400)).await;
+
+        // Then the event triggered an update, but polling stood down while connected
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+        let hits_while_connected = api_mock.hits();
+        assert!(hits_while_connected >= 2);
+        assert!(hits_while_connected <= 4);
+
+        // When the stream disconnects
+        close.notify_one();
+        sleep(std::time::Duration::from_millis(300)).await;
+
+        // Then polling resumes
+        assert!(api_mock.hits() >= hits_while_connected + 3);
+    }
+
+    #[tokio::test]
+    async fn failed_realtime_update_is_retried_by_polling_while_connected() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body.clone());
+        });
+        let close = Arc::new(tokio::sync::Notify::new());
+        let (realtime_api_url, connections) =
+            start_realtime_stand_in(Utc::now().timestamp() as f64, Arc::clone(&close)).await;
+
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            environment_refresh_interval_mills: 50,
+            environment_refresh_initial_backoff_mills: 20,
+            enable_local_evaluation: true,
+            enable_realtime_updates: true,
+            realtime_api_url,
+            ..Default::default()
+        };
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+
+        // When the update triggered by the event fails (the listener only starts
+        // once the test yields)
+        api_mock.delete();
+        let mut failing_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(500);
+        });
+        sleep(std::time::Duration::from_millis(300)).await;
+
+        // Then polling retries it while the stream stays connected
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+        assert!(failing_mock.hits() >= 3);
+
+        // When the API recovers
+        failing_mock.delete();
+        api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        sleep(std::time::Duration::from_millis(200)).await;
+
+        // Then polling stands down again
+        assert_eq!(flagsmith.consecutive_refresh_failures(), 0);
+        let hits = api_mock.hits();
+        sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(api_mock.hits(), hits);
+        close.notify_one();
+    }
+
+    #[tokio::test]
+    async fn realtime_event_older_than_environment_is_ignored() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+
+        let mock_server = MockServer::start();
+        let api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        // before the fixture's updated_at of 2023-07-14 16:12:00
+        let close = Arc::new(tokio::sync::Notify::new());
+        let (realtime_api_url, connections) =
+            start_realtime_stand_in(1689350000.0, Arc::clone(&close)).await;
+
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            enable_realtime_updates: true,
+            realtime_api_url,
+            ..Default::default()
+        };
+
+        // When
+        let _flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        sleep(std::time::Duration::from_millis(200)).await;
+
+        // Then
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+        api_mock.assert_hits(1);
+    }
+
+    #[tokio::test]
+    async fn subscribers_are_notified_of_environment_changes() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let mut updated_body = response_body.clone();
+        updated_body["updated_at"] = json!("2023-07-15 16:12:00.000000");
+        updated_body["feature_states"][0]["feature_state_value"] = json!("new-value");
+
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            ..Default::default()
+        };
+        let mut flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        let mut events = flagsmith.subscribe();
+        let mut some_feature = flagsmith.watch_flag("some_feature");
+        assert_eq!(
+            some_feature.borrow().as_ref().unwrap().value_as_string(),
+            Some("some-value".to_string())
+        );
+
+        // When the environment changes
+        api_mock.delete();
+        api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(updated_body);
+        });
+        flagsmith.update_environment().await.unwrap();
+
+        // Then
+        match events.recv().await.unwrap() {
+            EnvironmentEvent::Updated(change) => {
+                assert_eq!(change.changed_flags.len(), 1);
+                let flag_change = change.get("some_feature").unwrap();
+                assert_eq!(
+                    flag_change.previous.as_ref().unwrap().value_as_string(),
+                    Some("some-value".to_string())
+                );
+                assert_eq!(
+                    flag_change.current.as_ref().unwrap().value_as_string(),
+                    Some("new-value".to_string())
+                );
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        some_feature.changed().await.unwrap();
+        assert_eq!(
+            some_feature.borrow().as_ref().unwrap().value_as_string(),
+            Some("new-value".to_string())
+        );
+
+        // When a refresh fails
+        api_mock.delete();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(500);
+        });
+        assert!(flagsmith.update_environment().await.is_err());
+
+        // Then
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            EnvironmentEvent::RefreshFailed { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_local_evaluation_identity_override_evaluate_expected() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/environment-document/")
+                .header("X-Environment-Key", environment_key);
+            then.status(200).json_body(response_body);
+        });
+
+        let url = mock_server.url("/api/v1/");
+
+        let flagsmith_options = FlagsmithOptions {
+            api_url: url,
+            environment_refresh_interval_mills: 100,
+            enable_local_evaluation: true,
+            ..Default::default()
+        };
+
+        // When
+        let mut _flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+
+        // Then
+        let flags = _flagsmith.get_environment_flags();
+        let identity_flags = _flagsmith.get_identity_flags("overridden-id");
+        assert_eq!(
+            flags
+                .await
+                .unwrap()
+                .get_feature_value_as_string("some_feature")
+                .unwrap()
+                .to_owned(),
+            "some-value"
+        );
+        assert_eq!(
+            identity_flags
+                .await
+                .unwrap()
+                .get_feature_value_as_string("some_feature")
+                .unwrap()
+                .to_owned(),
+            "some-overridden-value"
+        );
+    }
+
+    #[tokio::test]
+    async fn identity_overrides_follow_the_latest_environment_document() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        // Editing only identity overrides does not change `updated_at`
+        let mut changed_body = response_body.clone();
+        changed_body["identity_overrides"][0]["identity_features"][0]["feature_state_value"] =
+            json!("some-changed-value");
+        let mut deleted_body = response_body.clone();
+        deleted_body["identity_overrides"] = json!([]);
+
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            ..Default::default()
+        };
+        let mut flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        let some_feature = |flags: models::Flags| flags.get_feature_value_as_string("some_feature");
+        assert_eq!(
+            some_feature(flagsmith.get_identity_flags("overridden-id").await.unwrap()).unwrap(),
+            "some-overridden-value"
+        );
+
+        // When the override is changed
+        api_mock.delete();
+        api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(changed_body);
+        });
+        flagsmith.update_environment().await.unwrap();
+
+        // Then
+        assert_eq!(
+            some_feature(flagsmith.get_identity_flags("overridden-id").await.unwrap()).unwrap(),
+            "some-changed-value"
+        );
+
+        // When the override is deleted
+        api_mock.delete();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(deleted_body);
+        });
+        flagsmith.update_environment().await.unwrap();
+
+        // Then
+        assert_eq!(
+            some_feature(flagsmith.get_identity_flags("overridden-id").await.unwrap()).unwrap(),
+            "some-value"
+        );
+        assert!(flagsmith
+            .datastore
+            .snapshot
+            .load()
+            .as_ref()
+            .unwrap()
+            .identities_with_overrides_by_identifier
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn explain_identity_flag_traces_identity_override() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            ..Default::default()
+        };
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+
+        // When
+        let trace = flagsmith
+            .explain_identity_flag("overridden-id", "some_feature")
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(trace.steps.len(), 2);
+        assert!(matches!(
+            trace.steps[0],
+            evaluation::EvaluationStep::Environment { enabled: true, .. }
+        ));
+        assert!(matches!(
+            trace.steps[1],
+            evaluation::EvaluationStep::IdentityOverride { enabled: false, .. }
+        ));
+        let flag = trace.flag.unwrap();
+        assert_eq!(flag.reason, models::EvaluationReason::IdentityOverride);
+        assert_eq!(flag.value_as_string().unwrap(), "some-overridden-value");
+        let identity_flags = flagsmith.get_identity_flags("overridden-id").await.unwrap();
+        assert!(matches!(
+            identity_flags.get_flag("test_mv").unwrap().reason,
+            models::EvaluationReason::MultivariateSplit { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn handler_defaults_report_why_they_were_used() {
+        // Given
+        struct Handler;
+        impl default_handler::DefaultHandler for Handler {
+            fn get_default(&self, feature_name: &str) -> models::Flag {
+                models::Flag {
+                    feature_name: feature_name.to_string(),
+                    is_default: true,
+                    ..Default::default()
+                }
+            }
+        }
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/flags/");
+            then.status(503);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            default_flag_handler: Some(Arc::new(Handler)),
+            ..Default::default()
+        };
+        let flagsmith =
+            Flagsmith::new("ser.test_environment_key".to_string(), flagsmith_options).await;
+
+        // When
+        let flag = flagsmith
+            .get_environment_flags()
+            .await
+            .unwrap()
+            .get_flag("some_feature")
+            .unwrap();
+
+        // Then
+        assert!(matches!(
+            flag.reason,
+            models::EvaluationReason::Error { message } if message.contains("503")
+        ));
+        assert!(matches!(
+            flagsmith
+                .explain_identity_flag("some-id", "some_feature")
+                .await,
+            Err(error::Error::LocalEvaluationRequired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn get_identities_flags_evaluates_batch_under_one_snapshot() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            batch_concurrency: 2,
+            ..Default::default()
+        };
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        let identities = ["first-id", "overridden-id", "second-id"];
+
+        // When
+        let results = flagsmith.get_identities_flags(identities).await;
+
+        // Then
+        let values: Vec<String> = results
+            .into_iter()
+            .map(|flags| {
+                flags
+                    .unwrap()
+                    .get_feature_value_as_string("some_feature")
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec!["some-value", "some-overridden-value", "some-value"]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_identities_flags_reports_errors_per_identity_in_remote_evaluation() {
+        // Given
+        let mock_server = MockServer::start();
+        let ok_mock = mock_server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/identities/")
+                .json_body_partial(r#"{"identifier": "good-id"}"#);
+            then.status(200).json_body(json!({
+                "flags": [{
+                    "feature_state_value": "remote-value",
+                    "feature": {"name": "some_feature", "id": 1},
+                    "enabled": true
+                }],
+                "traits": []
+            }));
+        });
+        let failing_mock = mock_server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/identities/")
+                .json_body_partial(r#"{"identifier": "bad-id"}"#);
+            then.status(400);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            ..Default::default()
+        };
+        let flagsmith =
+            Flagsmith::new("ser.test_environment_key".to_string(), flagsmith_options).await;
+        let identities = ["good-id", "bad-id", "good-id"];
+
+        // When
+        let results = flagsmith.get_identities_flags(identities).await;
+
+        // Then
+        ok_mock.assert_hits(2);
+        failing_mock.assert_hits(1);
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0]
+                .as_ref()
+                .unwrap()
+                .get_feature_value_as_string("some_feature")
+                .unwrap(),
+            "remote-value"
+        );
+        assert!(matches!(
+            &results[1],
+            Err(error::Error::Http { status, .. }) if status.as_u16() == 400
+        ));
+        assert!(results[2].is_ok());
+    }
+
+    #[tokio::test]
+    async fn remote_cache_serves_repeated_requests_without_hitting_the_api() {
+        // Given
+        let mock_server = MockServer::start();
+        let flags_body = json!([{
+            "feature_state_value": "remote-value",
+            "feature": {"name": "some_feature", "id": 1},
+            "enabled": true
+        }]);
+        let environment_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/flags/");
+            then.status(200).json_body(flags_body.clone());
+        });
+        let identity_mock = mock_server.mock(|when, then| {
+            when.method(POST).path("/api/v1/identities/");
+            then.status(200)
+                .json_body(json!({"flags": flags_body, "traits": []}));
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_remote_cache: true,
+            ..Default::default()
+        };
+        let flagsmith =
+            Flagsmith::new("ser.test_environment_key".to_string(), flagsmith_options).await;
+        let traits = || {
+            Some(vec![SDKTrait::new(
+                "plan".to_string(),
+                serde_json::from_value(json!("pro")).unwrap(),
+            )])
+        };
+
+        // When
+        for _ in 0..3 {
+            flagsmith.get_environment_flags().await.unwrap();
+            flagsmith
+                .get_identity_flags(("user", traits(), None))
+                .await
+                .unwrap();
+        }
+        flagsmith.get_identity_flags("user").await.unwrap();
+
+        // Then
+        environment_mock.assert_hits(1);
+        identity_mock.assert_hits(2);
+    }
+
+    #[tokio::test]
+    async fn environment_cache_is_written_and_used_when_the_api_is_down_on_start() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let cache_path = std::env::temp_dir().join(format!(
+            "flagsmith-environment-cache-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&cache_path);
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let flagsmith_options = || FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            environment_cache_path: Some(cache_path.clone()),
+            ..Default::default()
+        };
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options()).await;
+        drop(flagsmith);
+        assert!(cache_path.exists());
+
+        // When the client restarts during an outage
+        api_mock.delete();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(503);
+        });
+        let flagsmith = Flagsmith::try_new(environment_key.to_string(), flagsmith_options())
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(
+            flagsmith
+                .get_identity_flags("overridden-id")
+                .await
+                .unwrap()
+                .get_feature_value_as_string("some_feature")
+                .unwrap(),
+            "some-overridden-value"
+        );
+        assert_eq!(flagsmith.consecutive_refresh_failures(), 1);
+        let _ = std::fs::remove_file(&cache_path);
+        let _ = std::fs::remove_file(cache_path.with_extension("json.metadata"));
+    }
+
+    #[tokio::test]
+    async fn environment_cache_is_kept_fresh_by_unmodified_responses() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let etag = "\"v1\"";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let cache_path = std::env::temp_dir().join(format!(
+            "flagsmith-environment-cache-revalidated-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&cache_path);
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200)
+                .header("ETag", etag)
+                .json_body(response_body);
+        });
+        let flagsmith_options = FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            environment_cache_path: Some(cache_path.clone()),
+            environment_cache_max_age_seconds: 1,
+            ..Default::default()
+        };
+        let mut flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options).await;
+        api_mock.delete();
+        let conditional_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/environment-document/")
+                .header("If-None-Match", etag);
+            then.status(304);
+        });
+        sleep(Duration::from_millis(1200)).await;
+
+        // When
+        flagsmith.update_environment().await.unwrap();
+
+        // Then
+        conditional_mock.assert();
+        let environment_cache = EnvironmentCache::new(cache_path.clone(), Duration::from_secs(1));
+        assert!(environment_cache.load().await.is_some());
+        let _ = std::fs::remove_file(&cache_path);
+        let _ = std::fs::remove_file(cache_path.with_extension("json.metadata"));
+    }
+
+    #[tokio::test]
+    async fn environment_cache_is_revalidated_after_a_restart() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let etag = "\"v1\"";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let cache_path = std::env::temp_dir().join(format!(
+            "flagsmith-environment-cache-restarted-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&cache_path);
+        let mock_server = MockServer::start();
+        let unconditional_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/environment-document/")
+                .matches(|request| {
+                    !request
+                        .headers
+                        .iter()
+                        .flatten()
+                        .any(|(name, _)| name.eq_ignore_ascii_case("if-none-match"))
+                });
+            then.status(200)
+                .header("ETag", etag)
+                .json_body(response_body);
+        });
+        let conditional_mock = mock_server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/environment-document/")
+                .header("If-None-Match", etag);
+            then.status(304);
+        });
+        let flagsmith_options = || FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            environment_cache_path: Some(cache_path.clone()),
+            ..Default::default()
+        };
+        drop(Flagsmith::new(environment_key.to_string(), flagsmith_options()).await);
+
+        // When the client restarts
+        let flagsmith = Flagsmith::new(environment_key.to_string(), flagsmith_options()).await;
+
+        // Then the cached document is revalidated instead of downloaded again
+        unconditional_mock.assert_hits(1);
+        conditional_mock.assert_hits(1);
+        assert_eq!(
+            flagsmith
+                .get_environment_flags()
+                .await
+                .unwrap()
+                .get_feature_value_as_string("some_feature")
+                .unwrap(),
+            "some-value"
+        );
+        let _ = std::fs::remove_file(&cache_path);
+        let _ = std::fs::remove_file(cache_path.with_extension("json.metadata"));
+    }
+
+    #[tokio::test]
+    async fn followers_read_the_environment_written_by_the_leader() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let mut changed_body = response_body.clone();
+        changed_body["updated_at"] = json!("2023-07-15 16:12:00.000000");
+        changed_body["feature_states"][0]["feature_state_value"] = json!("changed-value");
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let environment_store: Arc<dyn EnvironmentStore> =
+            Arc::new(store::InMemoryEnvironmentStore::new());
+        let mut leader = Flagsmith::new(
+            environment_key.to_string(),
+            FlagsmithOptions {
+                api_url: mock_server.url("/api/v1/"),
+                enable_local_evaluation: true,
+                environment_store: Some(Arc::clone(&environment_store)),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // When
+        let follower = Flagsmith::new(
+            environment_key.to_string(),
+            FlagsmithOptions {
+                api_url: mock_server.url("/api/v1/"),
+                enable_local_evaluation: true,
+                environment_refresh_interval_mills: 10,
+                environment_store: Some(Arc::clone(&environment_store)),
+                environment_store_follower: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        let some_feature = |flags: Flags| flags.get_feature_value_as_string("some_feature");
+
+        // Then
+        assert_eq!(
+            some_feature(follower.get_environment_flags().await.unwrap()).unwrap(),
+            "some-value"
+        );
+        api_mock.assert_hits(1);
+
+        // When the leader fetches a new document
+        api_mock.delete();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(changed_body);
+        });
+        leader.update_environment().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+
+        // Then
+        assert_eq!(
+            some_feature(follower.get_environment_flags().await.unwrap()).unwrap(),
+            "changed-value"
+        );
+        assert_eq!(follower.consecutive_refresh_failures(), 0);
+    }
+
+    // Counts the reads of an in-memory store
+    #[derive(Default)]
+    struct CountingEnvironmentStore {
+        store: store::InMemoryEnvironmentStore,
+        reads: AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl EnvironmentStore for CountingEnvironmentStore {
+        async fn get(&self) -> Result<Option<StoredEnvironment>, error::Error> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.store.get().await
+        }
+
+        async fn put(&self, environment: StoredEnvironment) -> Result<(), error::Error> {
+            self.store.put(environment).await
+        }
+    }
+
+    #[tokio::test]
+    async fn leaders_only_start_from_recently_stored_environments() {
+        // Given an API that is down and a store written a day ago
+        let environment_key = "ser.test_environment_key";
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(503);
+        });
+        let environment_store = Arc::new(store::InMemoryEnvironmentStore::new());
+        let stored_environment = |stored_at: u64| StoredEnvironment {
+            document: serde_json::from_str(ENVIRONMENT_JSON).unwrap(),
+            metadata: EnvironmentMetadata::default(),
+            stored_at,
+        };
+        let now = Utc::now().timestamp() as u64;
+        environment_store
+            .put(stored_environment(now - 24 * 60 * 60))
+            .await
+            .unwrap();
+        let flagsmith_options = || FlagsmithOptions {
+            api_url: mock_server.url("/api/v1/"),
+            enable_local_evaluation: true,
+            environment_store: Some(environment_store.clone() as Arc<dyn EnvironmentStore>),
+            environment_store_max_age_seconds: 60 * 60,
+            ..Default::default()
+        };
+
+        // Then the stale document is not used
+        assert!(
+            Flagsmith::try_new(environment_key.to_string(), flagsmith_options())
+                .await
+                .is_err()
+        );
+
+        // When the store was written recently
+        environment_store
+            .put(stored_environment(now))
+            .await
+            .unwrap();
+
+        // Then
+        let flagsmith = Flagsmith::try_new(environment_key.to_string(), flagsmith_options())
+            .await
+            .unwrap();
+        assert!(flagsmith.get_environment_flags().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn followers_read_the_store_once_on_start() {
+        // Given
+        let environment_store = Arc::new(CountingEnvironmentStore::default());
+        environment_store
+            .put(StoredEnvironment {
+                document: serde_json::from_str(ENVIRONMENT_JSON).unwrap(),
+                metadata: EnvironmentMetadata::default(),
+                stored_at: Utc::now().timestamp() as u64,
+            })
+            .await
+            .unwrap();
+
+        // When
+        let follower = Flagsmith::new(
+            "ser.test_environment_key".to_string(),
+            FlagsmithOptions {
+                enable_local_evaluation: true,
+                environment_store: Some(environment_store.clone() as Arc<dyn EnvironmentStore>),
+                environment_store_follower: true,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Then
+        assert_eq!(environment_store.reads.load(Ordering::Relaxed), 1);
+        assert!(follower.get_environment_flags().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn followers_keep_retrying_an_empty_store() {
+        // Given a store the leader has not written yet
+        let environment_store = Arc::new(store::InMemoryEnvironmentStore::new());
+        let flagsmith_options = || FlagsmithOptions {
+            enable_local_evaluation: true,
+            environment_refresh_interval_mills: 60 * 60 * 1000,
+            environment_refresh_initial_backoff_mills: 10,
+            environment_store: Some(environment_store.clone() as Arc<dyn EnvironmentStore>),
+            environment_store_follower: true,
+            ..Default::default()
+        };
+        let err = Flagsmith::try_new("ser.test_environment_key".to_string(), flagsmith_options())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, error::Error::EmptyEnvironmentStore));
+        assert!(err.is_retryable());
+
+        // When a follower starts degraded and the leader publishes later
+        let follower = Flagsmith::builder("ser.test_environment_key".to_string())
+            .options(FlagsmithOptions {
+                allow_degraded_start: true,
+                ..flagsmith_options()
+            })
+            .build()
+            .await
+            .unwrap();
+        environment_store
+            .put(StoredEnvironment {
+                document: serde_json::from_str(ENVIRONMENT_JSON).unwrap(),
+                metadata: EnvironmentMetadata::default(),
+                stored_at: Utc::now().timestamp() as u64,
+            })
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+
+        // Then the follower picks it up without waiting for the next interval
+        assert!(follower.get_environment_flags().await.is_ok());
+        assert_eq!(follower.consecutive_refresh_failures(), 0);
+    }
+
+    #[tokio::test]
+    async fn local_reads_do_not_wait_for_the_async_runtime() {
+        // Given
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let mock_server = MockServer::start();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let flagsmith = Flagsmith::new(
+            environment_key.to_string(),
+            FlagsmithOptions {
+                api_url: mock_server.url("/api/v1/"),
+                enable_local_evaluation: true,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // When
+        let readers: Vec<_> = (0..8)
+            .map(|i| {
+                let local = flagsmith.local();
+                std::thread::spawn(move || {
+                    let identifier = format!("identity-{}", i);
+                    (
+                        local.environment_flags().unwrap(),
+                        local.identity_flags(identifier.as_str()).unwrap(),
+                        local.identity_segments(identifier.as_str()).unwrap(),
+                    )
+                })
+            })
+            .collect();
+
+        // Then
+        for reader in readers {
+            let (environment_flags, identity_flags, segments) = reader.join().unwrap();
+            assert_eq!(
+                environment_flags
+                    .get_feature_value_as_string("some_feature")
+                    .unwrap(),
+                "some-value"
+            );
+            assert_eq!(
+                identity_flags
+                    .get_feature_value_as_string("some_feature")
+                    .unwrap(),
+                "some-value"
+            );
+            assert!(segments.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn local_reads_require_a_local_environment() {
+        // Given
+        let flagsmith = Flagsmith::new(
+            "ser.test_environment_key".to_string(),
+            FlagsmithOptions::default(),
+        )
+        .await;
+
+        // Then
+        assert!(matches!(
+            flagsmith.local().environment_flags(),
+            Err(error::Error::LocalEvaluationRequired)
+        ));
+        assert!(matches!(
+            flagsmith.local().identity_flags("identity"),
+            Err(error::Error::LocalEvaluationRequired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn local_evaluator_reads_the_latest_environment_from_any_thread() {
+        // Given
+        fn assert_send_sync<T: Send + Sync + Clone + 'static>(_: &T) {}
+        let environment_key = "ser.test_environment_key";
+        let response_body: serde_json::Value = serde_json::from_str(ENVIRONMENT_JSON).unwrap();
+        let mut changed_body = response_body.clone();
+        changed_body["updated_at"] = json!("2023-07-15 16:12:00.000000");
+        changed_body["feature_states"][0]["feature_state_value"] = json!("changed-value");
+        let mock_server = MockServer::start();
+        let mut api_mock = mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(response_body);
+        });
+        let mut flagsmith = Flagsmith::new(
+            environment_key.to_string(),
+            FlagsmithOptions {
+                api_url: mock_server.url("/api/v1/"),
+                enable_local_evaluation: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        let local = flagsmith.local();
+        assert_send_sync(&local);
+        let some_feature = |local: &LocalEvaluator| {
+            local
+                .identity_flags("identity")
+                .unwrap()
+                .get_feature_value_as_string("some_feature")
+                .unwrap()
+        };
+        let evaluator = local.clone();
+        assert_eq!(
+            std::thread::spawn(move || some_feature(&evaluator))
+                .join()
+                .unwrap(),
+            "some-value"
+        );
+
+        // When
+        api_mock.delete();
+        mock_server.mock(|when, then| {
+            when.method(GET).path("/api/v1/environment-document/");
+            then.status(200).json_body(changed_body);
+        });
+        flagsmith.update_environment().await.unwrap();
+
+        // Then
+        let evaluator = local.clone();
+        assert_eq!(
+            std::thread::spawn(move || some_feature(&evaluator))
+                .join()
+                .unwrap(),
+            "changed-value"
+        );
+        assert_eq!(
+            local
+                .environment_flags()
+                .unwrap()
+                .get_feature_value_as_string("some_feature")
+                .unwrap(),
+            "changed-value"
+        );
+        assert!(local.identity_segments("identity").unwrap().is_empty());
+    }
 }
@@ -3,6 +3,7 @@ use core::f64;
 use flagsmith_flag_engine::features::FeatureState;
 use flagsmith_flag_engine::identities::Trait;
 use flagsmith_flag_engine::types::{FlagsmithValue, FlagsmithValueType};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -11,6 +12,34 @@ use crate::error;
 
 use super::default_handler;
 
+// Why a `Flag` has its value
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum EvaluationReason {
+    // The environment value of the feature
+    #[default]
+    Default,
+    // The identity overrides the feature
+    IdentityOverride,
+    // A segment the identity belongs to overrides the feature
+    SegmentOverride {
+        segment_name: String,
+    },
+    // The value was picked from the multivariate options of the feature state
+    // `origin` refers to, by the identity's hashed percentage
+    MultivariateSplit {
+        percentage: f32,
+        origin: Box<EvaluationReason>,
+    },
+    // The feature was not found and `default_flag_handler` provided the flag
+    HandlerDefault,
+    // The flags could not be retrieved and `default_flag_handler` provided the flag
+    Error {
+        message: String,
+    },
+    // The flag was evaluated by the Flagsmith API, which does not report a reason
+    Remote,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Flag {
     pub enabled: bool,
@@ -18,17 +47,19 @@ pub struct Flag {
     pub is_default: bool,
     pub feature_id: u32,
     pub feature_name: String,
+    pub reason: EvaluationReason,
 }
 
 impl Flag {
     pub fn from_feature_state(feature_state: FeatureState, identity_id: Option<&str>) -> Flag {
-        return Flag {
+        Flag {
             enabled: feature_state.enabled,
             value: feature_state.get_value(identity_id),
             is_default: false,
             feature_name: feature_state.feature.name,
             feature_id: feature_state.feature.id,
-        };
+            reason: EvaluationReason::Default,
+        }
     }
 
     pub fn from_api_flag(flag_json: &serde_json::Value) -> Option<Flag> {
@@ -41,6 +72,7 @@ impl Flag {
             feature_name: flag_json["feature"]["name"].as_str()?.to_string(),
             feature_id: flag_json["feature"]["id"].as_u64()?.try_into().ok()?,
             value,
+            reason: EvaluationReason::Remote,
         };
         Some(flag)
     }
@@ -60,9 +92,12 @@ impl Flag {
             _ => None,
         }
     }
+    // Integer values are also returned as floats
     pub fn value_as_f64(&self) -> Option<f64> {
         match self.value.value_type {
-            FlagsmithValueType::Float => Some(self.value.value.parse::<f64>().ok()?),
+            FlagsmithValueType::Float | FlagsmithValueType::Integer => {
+                Some(self.value.value.parse::<f64>().ok()?)
+            }
             _ => None,
         }
     }
@@ -72,6 +107,59 @@ impl Flag {
             _ => None,
         }
     }
+
+    // Deserializes the value into `T`. String values holding JSON (e.g. remote config
+    // objects) are parsed first, and integers can be read as floats.
+    // # Example
+    // ```
+    // #[derive(serde::Deserialize)]
+    // struct Banner {
+    //     title: String,
+    //     colour: String,
+    // }
+    // let banner: Banner = flag.value_as()?;
+    // ```
+    pub fn value_as<T: DeserializeOwned>(&self) -> Result<T, error::Error> {
+        let value = self.value_as_json();
+        let result = match &value {
+            serde_json::Value::String(string) => match serde_json::from_str(string) {
+                Ok(parsed) => Ok(parsed),
+                // Report why the JSON is invalid if the value looks like JSON,
+                // otherwise why the string itself does not fit `T`
+                Err(e) if looks_like_json(string) => Err(e),
+                Err(_) => serde_json::from_value(value),
+            },
+            _ => serde_json::from_value(value),
+        };
+        result.map_err(|source| error::Error::InvalidFlagValue {
+            name: self.feature_name.clone(),
+            source,
+        })
+    }
+
+    fn value_as_json(&self) -> serde_json::Value {
+        let value = &self.value.value;
+        match self.value.value_type {
+            FlagsmithValueType::Bool => value
+                .parse::<bool>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| value.as_str().into()),
+            FlagsmithValueType::Integer => value
+                .parse::<i64>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| value.as_str().into()),
+            FlagsmithValueType::Float => value
+                .parse::<f64>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| value.as_str().into()),
+            FlagsmithValueType::String => value.as_str().into(),
+            FlagsmithValueType::None => serde_json::Value::Null,
+        }
+    }
+}
+
+fn looks_like_json(value: &str) -> bool {
+    matches!(value.trim_start().chars().next(), Some('{') | Some('['))
 }
 
 #[derive(Clone)]
@@ -79,9 +167,42 @@ pub struct Flags {
     flags: HashMap<String, Flag>,
     analytics_processor: Option<AnalyticsProcessor>,
     default_flag_handler: Option<Arc<dyn default_handler::DefaultHandler + Send + Sync>>,
+    // Set when the flags could not be retrieved, so that handler defaults say why
+    error: Option<String>,
 }
 
 impl Flags {
+    pub(crate) fn from_flags(
+        flags: Vec<Flag>,
+        analytics_processor: Option<AnalyticsProcessor>,
+        default_flag_handler: Option<Arc<dyn default_handler::DefaultHandler + Send + Sync>>,
+    ) -> Flags {
+        Flags {
+            flags: flags
+                .into_iter()
+                .map(|flag| (flag.feature_name.clone(), flag))
+                .collect(),
+            analytics_processor,
+            default_flag_handler,
+            error: None,
+        }
+    }
+
+    // Returns flags holding only the defaults of `default_flag_handler`, used when
+    // the flags could not be retrieved because of `error`
+    pub(crate) fn from_error(
+        error: &error::Error,
+        analytics_processor: Option<AnalyticsProcessor>,
+        default_flag_handler: Option<Arc<dyn default_handler::DefaultHandler + Send + Sync>>,
+    ) -> Flags {
+        Flags {
+            flags: HashMap::new(),
+            analytics_processor,
+            default_flag_handler,
+            error: Some(error.to_string()),
+        }
+    }
+
     pub fn from_feature_states(
         feature_states: &Vec<FeatureState>,
         analytics_processor: Option<AnalyticsProcessor>,
@@ -95,11 +216,12 @@ impl Flags {
                 Flag::from_feature_state(feature_state.to_owned(), identity_id),
             );
         }
-        return Flags {
+        Flags {
             flags,
             analytics_processor,
             default_flag_handler,
-        };
+            error: None,
+        }
     }
     pub fn from_api_flags(
         api_flags: &Vec<serde_json::Value>,
@@ -111,16 +233,17 @@ impl Flags {
             let flag = Flag::from_api_flag(flag_json)?;
             flags.insert(flag.feature_name.clone(), flag);
         }
-        return Some(Flags {
+        Some(Flags {
             flags,
             analytics_processor,
             default_flag_handler,
-        });
+            error: None,
+        })
     }
 
     // Returns a vector of all `Flag` structs
     pub fn all_flags(&self) -> Vec<Flag> {
-        return self.flags.clone().into_values().collect();
+        self.flags.clone().into_values().collect()
     }
 
     // Check whether a given feature is enabled.
@@ -133,34 +256,55 @@ impl Flags {
     // Or error if the feature is not found
     pub fn get_feature_value_as_string(&self, feature_name: &str) -> Result<String, error::Error> {
         let flag = self.get_flag(feature_name)?;
-        return Ok(flag.value.value);
+        Ok(flag.value.value)
+    }
+
+    // Returns the value of a given feature deserialized into `T`, see `Flag::value_as`
+    pub fn get_config<T: DeserializeOwned>(&self, feature_name: &str) -> Result<T, error::Error> {
+        self.get_flag(feature_name)?.value_as()
+    }
+
+    // Materialises a typed set of flags, see `FromFlags`
+    pub fn typed<T: FromFlags>(&self) -> Result<T, error::Error> {
+        T::from_flags(self)
     }
 
     // Returns a specific `Flag` given the feature name
     pub fn get_flag(&self, feature_name: &str) -> Result<Flag, error::Error> {
-        match self.flags.get(&feature_name.to_string()) {
+        match self.flags.get(feature_name) {
             Some(flag) => {
-                if self.analytics_processor.is_some() && !flag.is_default {
-                    let _ = self
-                        .analytics_processor
-                        .as_ref()
-                        .unwrap()
-                        .tx
-                        .send(flag.feature_name.clone());
+                if let Some(analytics_processor) = &self.analytics_processor {
+                    if !flag.is_default {
+                        analytics_processor.track_feature(&flag.feature_name);
+                    }
                 };
-                return Ok(flag.clone());
+                Ok(flag.clone())
             }
             None => match &self.default_flag_handler {
-                Some(handler) => Ok(handler.get_default(feature_name)),
-                None => Err(error::Error::new(
-                    error::ErrorKind::FlagsmithAPIError,
-                    "API returned invalid response".to_string(),
-                )),
+                Some(handler) => {
+                    let mut flag = handler.get_default(feature_name);
+                    flag.reason = match &self.error {
+                        Some(message) => EvaluationReason::Error {
+                            message: message.clone(),
+                        },
+                        None => EvaluationReason::HandlerDefault,
+                    };
+                    Ok(flag)
+                }
+                None => Err(error::Error::FlagNotFound {
+                    name: feature_name.to_string(),
+                }),
             },
         }
     }
 }
 
+// Builds a value from `Flags`, usually implemented with `#[derive(FlagsmithFlags)]`
+// (requires the `derive` feature) so that features become typed fields
+pub trait FromFlags: Sized {
+    fn from_flags(flags: &Flags) -> Result<Self, error::Error>;
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SDKTrait {
     pub trait_key: String,
@@ -171,22 +315,28 @@ pub struct SDKTrait {
 
 impl SDKTrait {
     pub fn new(trait_key: String, trait_value: FlagsmithValue) -> SDKTrait {
-        return SDKTrait {
-            trait_key: trait_key,
-            trait_value: trait_value,
+        SDKTrait {
+            trait_key,
+            trait_value,
             transient: Default::default(),
-        };
+        }
     }
     pub fn new_with_transient(
         trait_key: String,
         trait_value: FlagsmithValue,
         transient: bool,
     ) -> Self {
-        return SDKTrait {
-            trait_key: trait_key,
-            trait_value: trait_value,
-            transient: transient,
-        };
+        SDKTrait {
+            trait_key,
+            trait_value,
+            transient,
+        }
+    }
+}
+
+impl From<Trait> for SDKTrait {
+    fn from(t: Trait) -> Self {
+        SDKTrait::new(t.trait_key, t.trait_value)
     }
 }
 
@@ -200,6 +350,7 @@ impl From<SDKTrait> for Trait {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     static FEATURE_STATE_JSON_STRING: &str = r#"{
@@ -378,4 +529,124 @@ mod tests {
         // Then
         assert_eq!(flag.value_as_i64().is_none(), true);
     }
+
+    fn flag_with_value(value: serde_json::Value) -> Flag {
+        Flag::from_api_flag(&serde_json::json!({
+            "feature_state_value": value,
+            "feature": {"name": "feature1", "id": 1},
+            "enabled": true
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn value_as_parses_json_string_values() {
+        // Given
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Banner {
+            title: String,
+            max_items: u32,
+        }
+        let flag = flag_with_value(serde_json::json!(r#"{"title": "Sale", "max_items": 3}"#));
+
+        // When
+        let banner: Banner = flag.value_as().unwrap();
+
+        // Then
+        assert_eq!(
+            banner,
+            Banner {
+                title: "Sale".to_string(),
+                max_items: 3
+            }
+        );
+    }
+
+    #[test]
+    fn value_as_reads_native_values() {
+        assert!(flag_with_value(serde_json::json!(true))
+            .value_as::<bool>()
+            .unwrap());
+        assert_eq!(
+            flag_with_value(serde_json::json!(10))
+                .value_as::<u8>()
+                .unwrap(),
+            10
+        );
+        assert_eq!(
+            flag_with_value(serde_json::json!("plain text"))
+                .value_as::<String>()
+                .unwrap(),
+            "plain text"
+        );
+        assert_eq!(
+            flag_with_value(serde_json::json!("42"))
+                .value_as::<i64>()
+                .unwrap(),
+            42
+        );
+    }
+
+    #[test]
+    fn integer_values_can_be_read_as_floats() {
+        // Given
+        let flag = flag_with_value(serde_json::json!(10));
+
+        // Then
+        assert_eq!(flag.value_as::<f64>().unwrap(), 10.0);
+        assert_eq!(flag.value_as_f64().unwrap(), 10.0);
+    }
+
+    #[test]
+    fn value_as_returns_descriptive_error_on_mismatch() {
+        // Given
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Banner {
+            title: String,
+        }
+
+        // When
+        let invalid_json = flag_with_value(serde_json::json!(r#"{"title": 1}"#))
+            .value_as::<Banner>()
+            .unwrap_err();
+        let wrong_type = flag_with_value(serde_json::json!(10.5))
+            .value_as::<bool>()
+            .unwrap_err();
+
+        // Then
+        assert!(matches!(
+            &invalid_json,
+            error::Error::InvalidFlagValue { name, .. } if name == "feature1"
+        ));
+        assert!(invalid_json
+            .to_string()
+            .contains("invalid type: integer `1`"));
+        assert!(wrong_type.to_string().contains("expected a boolean"));
+    }
+
+    #[test]
+    fn get_config_deserializes_flag_value() {
+        // Given
+        let flags = Flags::from_api_flags(
+            &vec![serde_json::json!({
+                "feature_state_value": r#"["a", "b"]"#,
+                "feature": {"name": "allowed_regions", "id": 1},
+                "enabled": true
+            })],
+            None,
+            None,
+        )
+        .unwrap();
+
+        // Then
+        assert_eq!(
+            flags.get_config::<Vec<String>>("allowed_regions").unwrap(),
+            vec!["a", "b"]
+        );
+        assert!(matches!(
+            flags.get_config::<Vec<String>>("missing").unwrap_err(),
+            error::Error::FlagNotFound { .. }
+        ));
+    }
 }
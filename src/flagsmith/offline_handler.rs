@@ -1,8 +1,28 @@
+use crate::error;
+use async_trait::async_trait;
 use flagsmith_flag_engine::environments::Environment;
+use flate2::read::GzDecoder;
 use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 pub trait OfflineHandler {
     fn get_environment(&self) -> Environment;
+
+    // How often the client should call `reload`, or `None` if the environment
+    // never changes
+    fn reload_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    // Returns the new environment if it changed since it was last read. Called
+    // from a blocking thread.
+    fn reload(&self) -> Result<Option<Environment>, error::Error> {
+        Ok(None)
+    }
 }
 
 pub struct LocalFileHandler {
@@ -30,6 +50,166 @@ impl OfflineHandler for LocalFileHandler {
     }
 }
 
+// Loads the environment asynchronously, e.g. from object storage or a secrets
+// manager. Errors are returned by `Flagsmith::try_new`.
+#[async_trait]
+pub trait AsyncOfflineHandler: Send + Sync {
+    async fn get_environment(&self) -> Result<Environment, error::Error>;
+}
+
+// Reads the environment from a document embedded in the binary, e.g. with
+// `include_bytes!("environment.json")`
+pub struct StaticBytesHandler {
+    environment_document: &'static [u8],
+}
+
+impl StaticBytesHandler {
+    pub fn new(environment_document: &'static [u8]) -> Self {
+        StaticBytesHandler {
+            environment_document,
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncOfflineHandler for StaticBytesHandler {
+    async fn get_environment(&self) -> Result<Environment, error::Error> {
+        Ok(serde_json::from_slice(self.environment_document)?)
+    }
+}
+
+// Reads the environment from a gzip-compressed document
+pub struct GzipFileHandler {
+    path: PathBuf,
+}
+
+impl GzipFileHandler {
+    pub fn new(environment_document_path: impl Into<PathBuf>) -> Self {
+        GzipFileHandler {
+            path: environment_document_path.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncOfflineHandler for GzipFileHandler {
+    async fn get_environment(&self) -> Result<Environment, error::Error> {
+        let read_error = |e: std::io::Error| {
+            error::Error::Client(format!("unable to read {}: {}", self.path.display(), e))
+        };
+        let compressed = tokio::fs::read(&self.path).await.map_err(read_error)?;
+        let mut environment_document = vec![];
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut environment_document)
+            .map_err(read_error)?;
+        Ok(serde_json::from_slice(&environment_document)?)
+    }
+}
+
+// Reads the environment from any `AsyncRead`, e.g. a network stream. The reader
+// is consumed on the first call; later calls return the same environment.
+pub struct AsyncReadHandler {
+    state: tokio::sync::Mutex<ReaderState>,
+}
+
+enum ReaderState {
+    Unread(Box<dyn AsyncRead + Send + Unpin>),
+    Read(Environment),
+}
+
+impl AsyncReadHandler {
+    pub fn new(reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        AsyncReadHandler {
+            state: tokio::sync::Mutex::new(ReaderState::Unread(Box::new(reader))),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncOfflineHandler for AsyncReadHandler {
+    async fn get_environment(&self) -> Result<Environment, error::Error> {
+        let mut state = self.state.lock().await;
+        let reader = match &mut *state {
+            ReaderState::Unread(reader) => reader,
+            ReaderState::Read(environment) => return Ok(environment.clone()),
+        };
+        let mut environment_document = vec![];
+        reader
+            .read_to_end(&mut environment_document)
+            .await
+            .map_err(|e| error::Error::Client(format!("unable to read environment: {}", e)))?;
+        let environment: Environment = serde_json::from_slice(&environment_document)?;
+        *state = ReaderState::Read(environment.clone());
+        Ok(environment)
+    }
+}
+
+// Reads the environment document from a file and reloads it whenever the file is
+// modified, e.g. to update the flags of air-gapped deployments without restarting
+pub struct WatchedFileHandler {
+    path: PathBuf,
+    poll_interval: Duration,
+    state: Mutex<WatchedFile>,
+}
+
+struct WatchedFile {
+    environment: Environment,
+    // Modification time and length of the last file read, whether it was valid or not
+    version: (SystemTime, u64),
+}
+
+impl WatchedFileHandler {
+    pub fn new(
+        environment_document_path: impl Into<PathBuf>,
+        poll_interval: Duration,
+    ) -> Result<Self, std::io::Error> {
+        let path = environment_document_path.into();
+        let version = file_version(&path)?;
+        let environment = serde_json::from_slice(&fs::read(&path)?)?;
+        Ok(WatchedFileHandler {
+            path,
+            poll_interval,
+            state: Mutex::new(WatchedFile {
+                environment,
+                version,
+            }),
+        })
+    }
+}
+
+fn file_version(path: &Path) -> Result<(SystemTime, u64), std::io::Error> {
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+impl OfflineHandler for WatchedFileHandler {
+    fn get_environment(&self) -> Environment {
+        self.state.lock().unwrap().environment.clone()
+    }
+
+    fn reload_interval(&self) -> Option<Duration> {
+        Some(self.poll_interval)
+    }
+
+    // Keeps the previous environment if the file cannot be read or parsed; the
+    // error is only returned once per modification of the file
+    fn reload(&self) -> Result<Option<Environment>, error::Error> {
+        let read_error = |e: std::io::Error| {
+            error::Error::Client(format!("unable to read {}: {}", self.path.display(), e))
+        };
+        let version = file_version(&self.path).map_err(read_error)?;
+        let mut state = self.state.lock().unwrap();
+        if state.version == version {
+            return Ok(None);
+        }
+        state.version = version;
+        let environment: Environment =
+            serde_json::from_slice(&fs::read(&self.path).map_err(read_error)?)?;
+        state.environment = environment.clone();
+        Ok(Some(environment))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,4 +221,79 @@ mod tests {
         let environment = handler.get_environment();
         assert_eq!(environment.api_key, "B62qaMZNwfiqT76p38ggrQ");
     }
+
+    #[tokio::test]
+    async fn async_handlers_read_the_environment_from_their_source() {
+        // Given
+        let static_bytes_handler =
+            StaticBytesHandler::new(include_bytes!("../../tests/fixtures/environment.json"));
+        let gzip_file_handler = GzipFileHandler::new("tests/fixtures/environment.json.gz");
+        let async_read_handler = AsyncReadHandler::new(
+            tokio::fs::File::open("tests/fixtures/environment.json")
+                .await
+                .unwrap(),
+        );
+
+        // Then
+        for handler in [
+            &static_bytes_handler as &dyn AsyncOfflineHandler,
+            &gzip_file_handler,
+            &async_read_handler,
+            &async_read_handler,
+        ] {
+            let environment = handler.get_environment().await.unwrap();
+            assert_eq!(environment.api_key, "B62qaMZNwfiqT76p38ggrQ");
+        }
+    }
+
+    #[tokio::test]
+    async fn async_handlers_return_errors_for_invalid_sources() {
+        // Given
+        let static_bytes_handler = StaticBytesHandler::new(b"{not json");
+        let gzip_file_handler = GzipFileHandler::new("tests/fixtures/environment.json");
+        let missing_file_handler = GzipFileHandler::new("tests/fixtures/missing.json.gz");
+
+        // Then
+        for handler in [
+            &static_bytes_handler as &dyn AsyncOfflineHandler,
+            &gzip_file_handler,
+            &missing_file_handler,
+        ] {
+            assert!(handler.get_environment().await.is_err());
+        }
+    }
+
+    #[test]
+    fn watched_file_handler_reloads_modified_files_and_keeps_valid_environment() {
+        // Given
+        let path = std::env::temp_dir().join(format!(
+            "flagsmith-watched-environment-{}.json",
+            std::process::id()
+        ));
+        let document = fs::read_to_string("tests/fixtures/environment.json").unwrap();
+        fs::write(&path, &document).unwrap();
+        let handler = WatchedFileHandler::new(&path, Duration::from_secs(1)).unwrap();
+        assert!(handler.reload().unwrap().is_none());
+
+        // When
+        fs::write(
+            &path,
+            document.replace("B62qaMZNwfiqT76p38ggrQ", "changed_key"),
+        )
+        .unwrap();
+        let reloaded = handler.reload().unwrap();
+
+        // Then
+        assert_eq!(reloaded.unwrap().api_key, "changed_key");
+        assert_eq!(handler.get_environment().api_key, "changed_key");
+
+        // When
+        fs::write(&path, "{not json").unwrap();
+
+        // Then
+        assert!(handler.reload().is_err());
+        assert!(handler.reload().unwrap().is_none());
+        assert_eq!(handler.get_environment().api_key, "changed_key");
+        let _ = fs::remove_file(&path);
+    }
 }
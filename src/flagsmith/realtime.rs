@@ -0,0 +1,295 @@
+use super::backoff::Backoff;
+use super::{update_environment, DataStore, RefreshStatus};
+use crate::error;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{debug, info, warn};
+use reqwest::header;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+const ENVIRONMENT_UPDATED_EVENT: &str = "environment_updated";
+// The stream sends keep-alive comments while idle, so a stream that stays silent
+// for several keep-alive intervals is treated as disconnected, e.g. a half-open
+// connection that would otherwise keep polling paused indefinitely
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+// Listens to the Flagsmith realtime stream and updates the environment as soon
+// as it changes. While connected, `connected` is set so that the polling
+// thread can stand down; it resumes polling when the stream drops or an update
+// fails.
+pub struct RealtimeListener {
+    pub stream_client: reqwest::Client,
+    pub realtime_api_url: String,
+    pub client: reqwest::Client,
+    pub datastore: Arc<DataStore>,
+    pub environment_url: String,
+    pub refresh_status: Arc<RefreshStatus>,
+    pub connected: Arc<AtomicBool>,
+    pub backoff: Backoff,
+}
+
+impl RealtimeListener {
+    pub async fn run(self, mut shutdown: watch::Receiver<()>) {
+        let mut failures = 0;
+        loop {
+            let result = tokio::select! {
+                _ = shutdown.changed() => break,
+                result = self.listen(&mut failures) => result,
+            };
+            self.connected.store(false, Ordering::Relaxed);
+            match result {
+                Ok(_) => debug!("Realtime stream closed"),
+                Err(e) => warn!("Realtime stream failed: {}", e),
+            }
+            failures += 1;
+            tokio::select! {
+                _ = shutdown.changed() => break,
+                _ = tokio::time::sleep(self.backoff.delay(failures)) => {}
+            }
+        }
+        self.connected.store(false, Ordering::Relaxed);
+        debug!("shutting down realtime listener");
+    }
+
+    async fn listen(&self, failures: &mut u32) -> Result<(), error::Error> {
+        // The stream is keyed by the client-side key from the environment document
+        let api_key = match self.datastore.environment() {
+            Some(environment) => environment.api_key.clone(),
+            None => {
+                return Err(error::Error::Client(
+                    "Environment required to subscribe to realtime updates.".to_string(),
+                ))
+            }
+        };
+        let url = format!(
+            "{}sse/environments/{}/stream",
+            self.realtime_api_url, api_key
+        );
+        let mut response = self
+            .stream_client
+            .get(url)
+            .header(header::ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(error::Error::Http {
+                status: response.status(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        info!("Connected to realtime updates");
+        self.connected.store(true, Ordering::Relaxed);
+        *failures = 0;
+
+        let mut parser = EventStreamParser::default();
+        while let Some(events) =
+            next_events(&mut response, &mut parser, STREAM_IDLE_TIMEOUT).await?
+        {
+            for event in events {
+                if event.event == ENVIRONMENT_UPDATED_EVENT {
+                    self.handle_environment_updated(&event.data).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_environment_updated(&self, data: &str) {
+        let updated_at = serde_json::from_str::<serde_json::Value>(data)
+            .ok()
+            .and_then(|data| data["updated_at"].as_f64())
+            .and_then(|seconds| DateTime::from_timestamp_millis((seconds * 1000.0) as i64));
+        let updated_at = match updated_at {
+            Some(updated_at) => updated_at,
+            None => {
+                warn!("Ignoring malformed realtime event: {}", data);
+                return;
+            }
+        };
+        let current_updated_at = self
+            .datastore
+            .environment_metadata
+            .lock()
+            .unwrap()
+            .updated_at
+            .as_deref()
+            .and_then(parse_updated_at);
+        if current_updated_at.is_some_and(|current| current >= updated_at) {
+            debug!("Environment is already up to date");
+            return;
+        }
+        let result = update_environment(&self.client, &self.datastore, &self.environment_url).await;
+        self.refresh_status.record(&result);
+        if let Err(e) = result {
+            warn!("Failed to update environment from realtime event: {}", e);
+        }
+    }
+}
+
+// Reads the next chunk of the stream and returns the events it completes, or
+// `None` once the stream ends. Fails if nothing is received for `idle_timeout`.
+async fn next_events(
+    response: &mut reqwest::Response,
+    parser: &mut EventStreamParser,
+    idle_timeout: Duration,
+) -> Result<Option<Vec<ServerSentEvent>>, error::Error> {
+    match tokio::time::timeout(idle_timeout, response.chunk()).await {
+        Ok(chunk) => Ok(chunk?.map(|chunk| parser.feed(&chunk))),
+        Err(_) => Err(error::Error::Client(format!(
+            "nothing received on the realtime stream for {}s",
+            idle_timeout.as_secs()
+        ))),
+    }
+}
+
+// Parses the `updated_at` of an environment document, which the API renders
+// either as RFC 3339 or as a naive UTC timestamp
+pub fn parse_updated_at(updated_at: &str) -> Option<DateTime<Utc>> {
+    if let Ok(updated_at) = DateTime::parse_from_rfc3339(updated_at) {
+        return Some(updated_at.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(updated_at, format).ok())
+        .map(|updated_at| updated_at.and_utc())
+}
+
+#[derive(Debug, PartialEq)]
+struct ServerSentEvent {
+    event: String,
+    data: String,
+}
+
+// Incrementally splits a `text/event-stream` body into events. Bytes are only
+// decoded once a whole line is buffered, since chunks may split a character.
+#[derive(Default)]
+struct EventStreamParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl EventStreamParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<ServerSentEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = vec![];
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(ServerSentEvent {
+                        event: self.event.take().unwrap_or_else(|| "message".to_string()),
+                        data: self.data.join("\n"),
+                    });
+                }
+                self.event = None;
+                self.data.clear();
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parser_emits_events_split_across_chunks() {
+        // Given
+        let mut parser = EventStreamParser::default();
+
+        // When
+        let first = parser.feed(b": keep-alive\n\nevent: environment_updated\r\ndata: {\"updated_");
+        let second = parser.feed(b"at\": 1.5}\r\n\r\ndata: plain\n\n");
+
+        // Then
+        assert!(first.is_empty());
+        assert_eq!(
+            second,
+            vec![
+                ServerSentEvent {
+                    event: "environment_updated".to_string(),
+                    data: "{\"updated_at\": 1.5}".to_string(),
+                },
+                ServerSentEvent {
+                    event: "message".to_string(),
+                    data: "plain".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parser_decodes_characters_split_across_chunks() {
+        // Given
+        let mut parser = EventStreamParser::default();
+        let line = "data: caf\u{e9}\n\n".as_bytes();
+        let split = line.len() - 3;
+
+        // When
+        let first = parser.feed(&line[..split]);
+        let second = parser.feed(&line[split..]);
+
+        // Then
+        assert!(first.is_empty());
+        assert_eq!(second[0].data, "caf\u{e9}");
+    }
+
+    #[tokio::test]
+    async fn silent_stream_fails_after_idle_timeout() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Given a stream that sends its headers and then nothing
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let _ = socket.read(&mut request).await;
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n")
+                .await
+                .unwrap();
+            std::future::pending::<()>().await;
+        });
+        let mut response = reqwest::get(url).await.unwrap();
+        let mut parser = EventStreamParser::default();
+
+        // When
+        let result = next_events(&mut response, &mut parser, Duration::from_millis(100)).await;
+
+        // Then
+        assert!(matches!(result, Err(error::Error::Client(_))));
+    }
+
+    #[test]
+    fn parse_updated_at_accepts_document_formats() {
+        let expected = DateTime::parse_from_rfc3339("2023-07-14T16:12:00.5Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        for updated_at in [
+            "2023-07-14 16:12:00.500000",
+            "2023-07-14T16:12:00.500000",
+            "2023-07-14T16:12:00.500000+00:00",
+        ] {
+            assert_eq!(parse_updated_at(updated_at), Some(expected));
+        }
+        assert_eq!(parse_updated_at("not a date"), None);
+    }
+}
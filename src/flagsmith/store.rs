@@ -0,0 +1,146 @@
+use super::environment_cache::write_atomically;
+use crate::error;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// Describes an environment document fetched from the API so that subsequent
+// requests can be made conditional on it having changed
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnvironmentMetadata {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+// An environment document as returned by the API, along with its metadata
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredEnvironment {
+    pub document: serde_json::Value,
+    pub metadata: EnvironmentMetadata,
+    // Seconds since the UNIX epoch at which the document was fetched from the API
+    #[serde(default)]
+    pub stored_at: u64,
+}
+
+// Stores the environment document shared between clients, e.g. so that a single
+// leader polls Flagsmith and writes the document that followers read (see
+// `FlagsmithOptions::environment_store_follower`). Implement it to share the
+// document through Redis or similar.
+#[async_trait]
+pub trait EnvironmentStore: Send + Sync {
+    // Returns the stored environment, or `None` if none was stored yet
+    async fn get(&self) -> Result<Option<StoredEnvironment>, error::Error>;
+    async fn put(&self, environment: StoredEnvironment) -> Result<(), error::Error>;
+}
+
+// Keeps the environment in memory, e.g. to share it between clients of the same process
+#[derive(Default)]
+pub struct InMemoryEnvironmentStore {
+    environment: Mutex<Option<StoredEnvironment>>,
+}
+
+impl InMemoryEnvironmentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl EnvironmentStore for InMemoryEnvironmentStore {
+    async fn get(&self) -> Result<Option<StoredEnvironment>, error::Error> {
+        Ok(self.environment.lock().unwrap().clone())
+    }
+
+    async fn put(&self, environment: StoredEnvironment) -> Result<(), error::Error> {
+        *self.environment.lock().unwrap() = Some(environment);
+        Ok(())
+    }
+}
+
+// Keeps the environment in a JSON file, e.g. on a volume shared between replicas.
+// Writes are atomic, so readers never see a partially written file.
+pub struct FileEnvironmentStore {
+    path: PathBuf,
+}
+
+impl FileEnvironmentStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileEnvironmentStore { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl EnvironmentStore for FileEnvironmentStore {
+    async fn get(&self) -> Result<Option<StoredEnvironment>, error::Error> {
+        match tokio::fs::read(&self.path).await {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(error::Error::Client(format!(
+                "unable to read {}: {}",
+                self.path.display(),
+                e
+            ))),
+        }
+    }
+
+    async fn put(&self, environment: StoredEnvironment) -> Result<(), error::Error> {
+        let contents = serde_json::to_vec(&environment)?;
+        write_atomically(&self.path, &contents).await.map_err(|e| {
+            error::Error::Client(format!("unable to write {}: {}", self.path.display(), e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored_environment() -> StoredEnvironment {
+        StoredEnvironment {
+            document: serde_json::json!({"api_key": "key"}),
+            metadata: EnvironmentMetadata {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: None,
+                updated_at: Some("2023-07-14 16:12:00.000000".to_string()),
+            },
+            stored_at: 1689350000,
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_returns_last_put_environment() {
+        // Given
+        let store = InMemoryEnvironmentStore::new();
+        assert_eq!(store.get().await.unwrap(), None);
+
+        // When
+        store.put(stored_environment()).await.unwrap();
+
+        // Then
+        assert_eq!(store.get().await.unwrap(), Some(stored_environment()));
+    }
+
+    #[tokio::test]
+    async fn file_store_round_trips_environment() {
+        // Given
+        let path = std::env::temp_dir().join(format!(
+            "flagsmith-environment-store-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let store = FileEnvironmentStore::new(&path);
+        assert_eq!(store.get().await.unwrap(), None);
+
+        // When
+        store.put(stored_environment()).await.unwrap();
+
+        // Then
+        assert_eq!(
+            FileEnvironmentStore::new(&path).get().await.unwrap(),
+            Some(stored_environment())
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,4 +1,26 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod error;
 pub mod flagsmith;
-pub use crate::flagsmith::models::Flag;
-pub use crate::flagsmith::{default_handler::DefaultHandler, Flagsmith, FlagsmithOptions};
+pub use crate::flagsmith::context::IdentityContext;
+pub use crate::flagsmith::models::{EvaluationReason, Flag, FromFlags};
+pub use crate::flagsmith::{
+    default_handler::DefaultHandler, Flagsmith, FlagsmithBuilder, FlagsmithOptions, LocalEvaluator,
+};
+#[cfg(feature = "derive")]
+pub use flagsmith_derive::FlagsmithFlags;
+
+// Used by code generated by `#[derive(FlagsmithFlags)]`, not part of the public API
+#[doc(hidden)]
+pub mod __private {
+    use crate::flagsmith::models::{Flag, Flags};
+
+    // Returns the flag to read a field from, or `None` if the field should fall back
+    // to its default. A field default takes precedence over the default flag handler.
+    pub fn lookup(flags: &Flags, feature_name: &str, has_default: bool) -> Option<Flag> {
+        match flags.get_flag(feature_name) {
+            Ok(flag) if !(flag.is_default && has_default) => Some(flag),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use flagsmith::error::Error;
+use flagsmith::flagsmith::models::Flags;
+use flagsmith::{DefaultHandler, Flag};
+use flagsmith_derive::FlagsmithFlags;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Banner {
+    title: String,
+}
+
+#[derive(FlagsmithFlags, Debug)]
+struct Features {
+    new_checkout: bool,
+    #[flagsmith(name = "max_cart_items")]
+    max_items: u32,
+    #[flagsmith(value)]
+    dark_mode: bool,
+    banner: Banner,
+    #[flagsmith(default = "en".to_string())]
+    locale: String,
+    #[flagsmith(default = None)]
+    r#type: Option<String>,
+}
+
+fn api_flag(name: &str, enabled: bool, value: serde_json::Value) -> serde_json::Value {
+    json!({
+        "feature_state_value": value,
+        "feature": {"name": name, "id": 1},
+        "enabled": enabled
+    })
+}
+
+fn flags(api_flags: Vec<serde_json::Value>) -> Flags {
+    Flags::from_api_flags(&api_flags, None, None).unwrap()
+}
+
+#[test]
+fn derived_struct_is_read_from_flags() {
+    // Given
+    let flags = flags(vec![
+        api_flag("new_checkout", true, json!(null)),
+        api_flag("max_cart_items", true, json!(25)),
+        api_flag("dark_mode", false, json!(true)),
+        api_flag("banner", true, json!(r#"{"title": "Sale"}"#)),
+        api_flag("type", true, json!("premium")),
+    ]);
+
+    // When
+    let features: Features = flags.typed().unwrap();
+
+    // Then
+    assert!(features.new_checkout);
+    assert_eq!(features.max_items, 25);
+    assert!(features.dark_mode);
+    assert_eq!(
+        features.banner,
+        Banner {
+            title: "Sale".to_string()
+        }
+    );
+    assert_eq!(features.locale, "en");
+    assert_eq!(features.r#type, Some("premium".to_string()));
+}
+
+#[test]
+fn missing_and_mistyped_features_are_all_reported() {
+    // Given
+    let flags = flags(vec![
+        api_flag("new_checkout", true, json!(null)),
+        api_flag("max_cart_items", true, json!("lots")),
+        api_flag("dark_mode", true, json!(true)),
+    ]);
+
+    // When
+    let err = flags.typed::<Features>().unwrap_err();
+
+    // Then
+    let errors = match err {
+        Error::InvalidFlags { errors } => errors,
+        err => panic!("unexpected error {:?}", err),
+    };
+    assert_eq!(errors.len(), 2);
+    assert!(matches!(
+        &errors[0],
+        Error::InvalidFlagValue { name, .. } if name == "max_cart_items"
+    ));
+    assert!(matches!(&errors[1], Error::FlagNotFound { name } if name == "banner"));
+}
+
+struct LimitsDefaultHandler;
+
+impl DefaultHandler for LimitsDefaultHandler {
+    fn get_default(&self, feature_name: &str) -> Flag {
+        Flag {
+            feature_name: feature_name.to_string(),
+            enabled: true,
+            is_default: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(FlagsmithFlags)]
+struct Limits {
+    unlimited: bool,
+    #[flagsmith(default = false)]
+    beta: bool,
+}
+
+#[test]
+fn field_defaults_take_precedence_over_default_handler() {
+    // Given
+    let flags = Flags::from_api_flags(&vec![], None, Some(Arc::new(LimitsDefaultHandler))).unwrap();
+
+    // When
+    let limits: Limits = flags.typed().unwrap();
+
+    // Then
+    assert!(limits.unlimited);
+    assert!(!limits.beta);
+}
@@ -2,12 +2,16 @@ use std::sync::Arc;
 
 use httpmock::prelude::*;
 use rstest::*;
+#[allow(clippy::single_component_path_imports)]
 use serde_json;
 
 use flagsmith::{
+    error::Error,
     flagsmith::default_handler::{self, DefaultHandler},
-    Flagsmith, FlagsmithOptions,
+    flagsmith::models::Flags,
+    Flagsmith, FlagsmithOptions, IdentityContext,
 };
+use flagsmith_flag_engine::segments::Segment;
 pub static FEATURE_1_NAME: &str = "feature_1";
 pub static FEATURE_1_ID: u32 = 1;
 pub static FEATURE_1_STR_VALUE: &str = "some_value";
@@ -141,6 +145,7 @@ pub fn identities_json() -> serde_json::Value {
 struct FeatureDefault {}
 
 impl DefaultHandler for FeatureDefault {
+    #[allow(clippy::field_reassign_with_default, clippy::needless_return)]
     fn get_default(&self, _feature_name: &str) -> flagsmith::Flag {
         let mut default_flag = flagsmith::Flag::default();
         default_flag.enabled = true;
@@ -161,11 +166,11 @@ pub fn mock_server() -> MockServer {
     MockServer::start()
 }
 
-#[fixture]
 pub async fn local_eval_flagsmith(
+    client: ClientKind,
     environment_json: serde_json::Value,
     mock_server: MockServer,
-) -> Flagsmith {
+) -> TestFlagsmith {
     // Given
     let _api_mock = mock_server.mock(|when, then| {
         when.method(GET)
@@ -179,8 +184,147 @@ pub async fn local_eval_flagsmith(
         enable_local_evaluation: true,
         ..Default::default()
     };
-    let mut flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let mut flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     flagsmith.update_environment().await.unwrap();
-    return flagsmith;
+    flagsmith
+}
+
+// The clients that the integration tests run against
+#[derive(Clone, Copy, Debug)]
+pub enum ClientKind {
+    Async,
+    #[cfg(feature = "blocking")]
+    Blocking,
+}
+
+// Exposes both clients through the async client's methods, so that a test runs
+// against either. The blocking client is called from a blocking thread, since it
+// must not be called from within the test's runtime.
+pub enum TestFlagsmith {
+    Async(Box<Flagsmith>),
+    #[cfg(feature = "blocking")]
+    Blocking(Option<Arc<std::sync::Mutex<flagsmith::blocking::Flagsmith>>>),
+}
+
+impl TestFlagsmith {
+    pub async fn new(
+        client: ClientKind,
+        environment_key: String,
+        flagsmith_options: FlagsmithOptions,
+    ) -> Self {
+        match client {
+            ClientKind::Async => {
+                let flagsmith = Flagsmith::new(environment_key, flagsmith_options).await;
+                TestFlagsmith::Async(Box::new(flagsmith))
+            }
+            #[cfg(feature = "blocking")]
+            ClientKind::Blocking => TestFlagsmith::Blocking(Some(Arc::new(std::sync::Mutex::new(
+                run_blocking(move || {
+                    flagsmith::blocking::Flagsmith::new(environment_key, flagsmith_options)
+                })
+                .await,
+            )))),
+        }
+    }
+
+    pub async fn try_new(
+        client: ClientKind,
+        environment_key: String,
+        flagsmith_options: FlagsmithOptions,
+    ) -> Result<Self, Error> {
+        match client {
+            ClientKind::Async => Flagsmith::try_new(environment_key, flagsmith_options)
+                .await
+                .map(|flagsmith| TestFlagsmith::Async(Box::new(flagsmith))),
+            #[cfg(feature = "blocking")]
+            ClientKind::Blocking => run_blocking(move || {
+                flagsmith::blocking::Flagsmith::try_new(environment_key, flagsmith_options)
+            })
+            .await
+            .map(|flagsmith| {
+                TestFlagsmith::Blocking(Some(Arc::new(std::sync::Mutex::new(flagsmith))))
+            }),
+        }
+    }
+
+    pub async fn get_environment_flags(&self) -> Result<Flags, Error> {
+        match self {
+            TestFlagsmith::Async(flagsmith) => flagsmith.get_environment_flags().await,
+            #[cfg(feature = "blocking")]
+            TestFlagsmith::Blocking(flagsmith) => {
+                with_blocking(flagsmith, |flagsmith| flagsmith.get_environment_flags()).await
+            }
+        }
+    }
+
+    pub async fn get_identity_flags(
+        &self,
+        context: impl Into<IdentityContext>,
+    ) -> Result<Flags, Error> {
+        let context = context.into();
+        match self {
+            TestFlagsmith::Async(flagsmith) => flagsmith.get_identity_flags(context).await,
+            #[cfg(feature = "blocking")]
+            TestFlagsmith::Blocking(flagsmith) => {
+                with_blocking(flagsmith, |flagsmith| flagsmith.get_identity_flags(context)).await
+            }
+        }
+    }
+
+    pub async fn get_identity_segments(
+        &self,
+        context: impl Into<IdentityContext>,
+    ) -> Result<Vec<Segment>, Error> {
+        let context = context.into();
+        match self {
+            TestFlagsmith::Async(flagsmith) => flagsmith.get_identity_segments(context).await,
+            #[cfg(feature = "blocking")]
+            TestFlagsmith::Blocking(flagsmith) => {
+                with_blocking(flagsmith, |flagsmith| {
+                    flagsmith.get_identity_segments(context)
+                })
+                .await
+            }
+        }
+    }
+
+    pub async fn update_environment(&mut self) -> Result<(), Error> {
+        match self {
+            TestFlagsmith::Async(flagsmith) => flagsmith.update_environment().await,
+            #[cfg(feature = "blocking")]
+            TestFlagsmith::Blocking(flagsmith) => {
+                with_blocking(flagsmith, |flagsmith| flagsmith.update_environment()).await
+            }
+        }
+    }
+}
+
+// The blocking client shuts down its runtime when dropped, which is not allowed
+// from within the test's runtime
+#[cfg(feature = "blocking")]
+impl Drop for TestFlagsmith {
+    fn drop(&mut self) {
+        if let TestFlagsmith::Blocking(flagsmith) = self {
+            let flagsmith = flagsmith.take();
+            std::thread::spawn(move || drop(flagsmith)).join().unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "blocking")]
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+#[cfg(feature = "blocking")]
+async fn with_blocking<T: Send + 'static>(
+    flagsmith: &Option<Arc<std::sync::Mutex<flagsmith::blocking::Flagsmith>>>,
+    f: impl FnOnce(&mut flagsmith::blocking::Flagsmith) -> T + Send + 'static,
+) -> T {
+    let flagsmith = Arc::clone(flagsmith.as_ref().unwrap());
+    run_blocking(move || f(&mut flagsmith.lock().unwrap())).await
 }
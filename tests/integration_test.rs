@@ -1,8 +1,12 @@
+#![allow(clippy::bool_assert_comparison, clippy::unit_arg)]
+
 use std::sync::Arc;
 
+use flagsmith::error::{ConfigurationError, Error, ErrorKind};
+use flagsmith::flagsmith::changes::EnvironmentEvent;
 use flagsmith::flagsmith::models::SDKTrait;
-use flagsmith::flagsmith::{default_handler, offline_handler};
-use flagsmith::{Flagsmith, FlagsmithOptions};
+use flagsmith::flagsmith::{default_handler, offline_handler, store};
+use flagsmith::{Flagsmith, FlagsmithOptions, IdentityContext};
 use flagsmith_flag_engine::identities::Trait;
 use flagsmith_flag_engine::types::{FlagsmithValue, FlagsmithValueType};
 
@@ -17,12 +21,17 @@ use fixtures::flags_json;
 use fixtures::identities_json;
 use fixtures::local_eval_flagsmith;
 use fixtures::mock_server;
+use fixtures::ClientKind;
+use fixtures::TestFlagsmith;
 use fixtures::ENVIRONMENT_KEY;
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 #[should_panic(expected = "default_flag_handler cannot be used with offline_handler")]
 async fn test_flagsmith_panics_if_both_default_handler_and_offline_hanlder_are_set(
+    #[case] client: ClientKind,
     default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
 ) {
     let handler =
@@ -32,24 +41,32 @@ async fn test_flagsmith_panics_if_both_default_handler_and_offline_hanlder_are_s
         offline_handler: Some(Box::new(handler)),
         ..Default::default()
     };
-    Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 #[should_panic(expected = "offline_handler must be set to use offline_mode")]
-async fn test_flagsmith_panics_if_offline_mode_is_used_without_offline_hanlder() {
+async fn test_flagsmith_panics_if_offline_mode_is_used_without_offline_hanlder(
+    #[case] client: ClientKind,
+) {
     let flagsmith_options = FlagsmithOptions {
         offline_mode: true,
         ..Default::default()
     };
-    Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 #[should_panic(expected = "offline_handler cannot be used with local evaluation")]
-async fn test_flagsmith_should_panic_if_local_evaluation_mode_is_used_with_offline_handler() {
+async fn test_flagsmith_should_panic_if_local_evaluation_mode_is_used_with_offline_handler(
+    #[case] client: ClientKind,
+) {
     let handler =
         offline_handler::LocalFileHandler::new("tests/fixtures/environment.json").unwrap();
     let flagsmith_options = FlagsmithOptions {
@@ -57,12 +74,233 @@ async fn test_flagsmith_should_panic_if_local_evaluation_mode_is_used_with_offli
         offline_handler: Some(Box::new(handler)),
         ..Default::default()
     };
-    Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_builder_returns_configuration_error_for_invalid_options(
+    default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
+) {
+    // Given
+    let cases = vec![
+        (
+            FlagsmithOptions {
+                offline_mode: true,
+                ..Default::default()
+            },
+            ConfigurationError::OfflineModeWithoutOfflineHandler,
+        ),
+        (
+            FlagsmithOptions {
+                default_flag_handler: Some(default_flag_handler),
+                offline_handler: Some(Box::new(
+                    offline_handler::LocalFileHandler::new("tests/fixtures/environment.json")
+                        .unwrap(),
+                )),
+                ..Default::default()
+            },
+            ConfigurationError::DefaultHandlerWithOfflineHandler,
+        ),
+        (
+            FlagsmithOptions {
+                enable_local_evaluation: true,
+                offline_handler: Some(Box::new(
+                    offline_handler::LocalFileHandler::new("tests/fixtures/environment.json")
+                        .unwrap(),
+                )),
+                ..Default::default()
+            },
+            ConfigurationError::LocalEvaluationWithOfflineHandler,
+        ),
+        (
+            FlagsmithOptions {
+                enable_realtime_updates: true,
+                ..Default::default()
+            },
+            ConfigurationError::RealtimeWithoutLocalEvaluation,
+        ),
+        (
+            FlagsmithOptions {
+                offline_fallback: true,
+                ..Default::default()
+            },
+            ConfigurationError::OfflineFallbackWithoutOfflineHandler,
+        ),
+        (
+            FlagsmithOptions {
+                offline_mode: true,
+                offline_fallback: true,
+                offline_handler: Some(Box::new(
+                    offline_handler::LocalFileHandler::new("tests/fixtures/environment.json")
+                        .unwrap(),
+                )),
+                ..Default::default()
+            },
+            ConfigurationError::OfflineFallbackWithOfflineMode,
+        ),
+        (
+            FlagsmithOptions {
+                offline_handler: Some(Box::new(
+                    offline_handler::LocalFileHandler::new("tests/fixtures/environment.json")
+                        .unwrap(),
+                )),
+                async_offline_handler: Some(Arc::new(offline_handler::GzipFileHandler::new(
+                    "tests/fixtures/environment.json.gz",
+                ))),
+                ..Default::default()
+            },
+            ConfigurationError::MultipleOfflineHandlers,
+        ),
+        (
+            FlagsmithOptions {
+                environment_store: Some(Arc::new(store::InMemoryEnvironmentStore::new())),
+                ..Default::default()
+            },
+            ConfigurationError::EnvironmentStoreWithoutLocalEvaluation,
+        ),
+        (
+            FlagsmithOptions {
+                enable_local_evaluation: true,
+                environment_store_follower: true,
+                ..Default::default()
+            },
+            ConfigurationError::FollowerWithoutEnvironmentStore,
+        ),
+        (
+            FlagsmithOptions {
+                enable_local_evaluation: true,
+                enable_realtime_updates: true,
+                environment_store: Some(Arc::new(store::InMemoryEnvironmentStore::new())),
+                environment_store_follower: true,
+                ..Default::default()
+            },
+            ConfigurationError::RealtimeWithFollower,
+        ),
+    ];
+    for (flagsmith_options, expected) in cases {
+        // When
+        let err = Flagsmith::builder(ENVIRONMENT_KEY.to_string())
+            .options(flagsmith_options)
+            .build()
+            .await
+            .err()
+            .unwrap();
+
+        // Then
+        assert_eq!(err.kind(), ErrorKind::FlagsmithConfigurationError(expected));
+    }
+}
+
+#[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
+#[tokio::test]
+async fn test_try_new_returns_configuration_error_for_invalid_environment_key(
+    #[case] client: ClientKind,
+) {
+    // When
+    let err = TestFlagsmith::try_new(
+        client,
+        "invalid\nkey".to_string(),
+        FlagsmithOptions::default(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    // Then
+    assert!(matches!(
+        err,
+        Error::Configuration(ConfigurationError::InvalidEnvironmentKey)
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_builder_returns_error_if_initial_environment_fetch_fails(mock_server: MockServer) {
+    // Given
+    let api_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/environment-document/");
+        then.status(503);
+    });
+    let flagsmith_options = FlagsmithOptions {
+        api_url: mock_server.url("/api/v1/"),
+        enable_local_evaluation: true,
+        ..Default::default()
+    };
+
+    // When
+    let err = Flagsmith::builder(ENVIRONMENT_KEY.to_string())
+        .options(flagsmith_options)
+        .build()
+        .await
+        .err()
+        .unwrap();
+
+    // Then
+    assert_eq!(err.kind(), ErrorKind::FlagsmithAPIError);
+    assert!(matches!(err, Error::Http { status, .. } if status.as_u16() == 503));
+    assert!(err.is_retryable());
+    api_mock.assert();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_builder_starts_degraded_and_recovers_in_the_background(
+    mock_server: MockServer,
+    environment_json: serde_json::Value,
+    flags_json: serde_json::Value,
+) {
+    // Given
+    let mut failing_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/environment-document/");
+        then.status(503);
+    });
+    let flags_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/flags/");
+        then.status(200).json_body(flags_json);
+    });
+    let flagsmith_options = FlagsmithOptions {
+        api_url: mock_server.url("/api/v1/"),
+        enable_local_evaluation: true,
+        environment_refresh_interval_mills: 100,
+        environment_refresh_initial_backoff_mills: 50,
+        environment_refresh_max_backoff_mills: 50,
+        allow_degraded_start: true,
+        ..Default::default()
+    };
+
+    // When
+    let flagsmith = Flagsmith::builder(ENVIRONMENT_KEY.to_string())
+        .options(flagsmith_options)
+        .build()
+        .await
+        .unwrap();
+
+    // Then, without an environment the flags are fetched from the API
+    flagsmith.get_environment_flags().await.unwrap();
+    assert!(failing_mock.hits() >= 1);
+    flags_mock.assert_hits(1);
+
+    // And once the API recovers, the polling thread loads the environment
+    failing_mock.delete();
+    let environment_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/environment-document/");
+        then.status(200).json_body(environment_json);
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    assert!(environment_mock.hits() >= 1);
+    flagsmith.get_environment_flags().await.unwrap();
+    flags_mock.assert_hits(1);
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_get_environment_flags_uses_local_environment_when_available(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     environment_json: serde_json::Value,
 ) {
@@ -78,7 +316,8 @@ async fn test_get_environment_flags_uses_local_environment_when_available(
         api_url: url,
         ..Default::default()
     };
-    let mut flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let mut flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
 
@@ -97,8 +336,11 @@ async fn test_get_environment_flags_uses_local_environment_when_available(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_get_environment_flags_calls_api_when_no_local_environment(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     flags_json: serde_json::Value,
 ) {
@@ -114,7 +356,8 @@ async fn test_get_environment_flags_calls_api_when_no_local_environment(
         api_url: url,
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let all_flags = flagsmith.get_environment_flags().await.unwrap().all_flags();
@@ -131,8 +374,10 @@ async fn test_get_environment_flags_calls_api_when_no_local_environment(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
-async fn test_offline_mode() {
+async fn test_offline_mode(#[case] client: ClientKind) {
     // Given
     let handler =
         offline_handler::LocalFileHandler::new("tests/fixtures/environment.json").unwrap();
@@ -141,12 +386,13 @@ async fn test_offline_mode() {
         ..Default::default()
     };
 
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let env_flags = flagsmith.get_environment_flags().await.unwrap().all_flags();
     let identity_flags = flagsmith
-        .get_identity_flags("test_identity", None, None)
+        .get_identity_flags("test_identity")
         .await
         .unwrap()
         .all_flags();
@@ -171,8 +417,13 @@ async fn test_offline_mode() {
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
-async fn test_offline_handler_is_used_if_request_fails(mock_server: MockServer) {
+async fn test_offline_handler_is_used_if_request_fails(
+    #[case] client: ClientKind,
+    mock_server: MockServer,
+) {
     let url = mock_server.url("/api/v1/");
     let handler =
         offline_handler::LocalFileHandler::new("tests/fixtures/environment.json").unwrap();
@@ -182,12 +433,13 @@ async fn test_offline_handler_is_used_if_request_fails(mock_server: MockServer)
         ..Default::default()
     };
 
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let env_flags = flagsmith.get_environment_flags().await.unwrap().all_flags();
     let identity_flags = flagsmith
-        .get_identity_flags("test_identity", None, None)
+        .get_identity_flags("test_identity")
         .await
         .unwrap()
         .all_flags();
@@ -212,8 +464,11 @@ async fn test_offline_handler_is_used_if_request_fails(mock_server: MockServer)
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_get_identity_flags_uses_local_environment_when_available(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     environment_json: serde_json::Value,
 ) {
@@ -229,7 +484,8 @@ async fn test_get_identity_flags_uses_local_environment_when_available(
         api_url: url,
         ..Default::default()
     };
-    let mut flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let mut flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
 
@@ -237,7 +493,7 @@ async fn test_get_identity_flags_uses_local_environment_when_available(
 
     // Then
     let all_flags = flagsmith
-        .get_identity_flags("test_identity", None, None)
+        .get_identity_flags("test_identity")
         .await
         .unwrap()
         .all_flags();
@@ -252,8 +508,11 @@ async fn test_get_identity_flags_uses_local_environment_when_available(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_get_identity_flags_calls_api_when_no_local_environment_no_traits(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     identities_json: serde_json::Value,
 ) {
@@ -275,12 +534,13 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_no_traits(
         api_url: url,
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
 
     let all_flags = flagsmith
-        .get_identity_flags(identifier, None, None)
+        .get_identity_flags(identifier)
         .await
         .unwrap()
         .all_flags();
@@ -298,8 +558,11 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_no_traits(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_get_identity_flags_calls_api_when_no_local_environment_with_traits(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     identities_json: serde_json::Value,
 ) {
@@ -325,7 +588,8 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_traits
         api_url: url,
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let traits = vec![SDKTrait::new(
@@ -336,7 +600,7 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_traits
         },
     )];
     let all_flags = flagsmith
-        .get_identity_flags(identifier, Some(traits), None)
+        .get_identity_flags((identifier, Some(traits), None))
         .await
         .unwrap()
         .all_flags();
@@ -354,8 +618,11 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_traits
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transient_traits(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     identities_json: serde_json::Value,
 ) {
@@ -385,7 +652,8 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transi
         api_url: url,
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let traits = vec![
@@ -406,7 +674,7 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transi
         ),
     ];
     flagsmith
-        .get_identity_flags(identifier, Some(traits), None)
+        .get_identity_flags((identifier, Some(traits), None))
         .await
         .unwrap()
         .all_flags();
@@ -416,8 +684,11 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transi
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transient_identity(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     identities_json: serde_json::Value,
 ) {
@@ -443,7 +714,8 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transi
         api_url: url,
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let traits = vec![SDKTrait::new(
@@ -454,7 +726,7 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transi
         },
     )];
     flagsmith
-        .get_identity_flags(identifier, Some(traits), Some(true))
+        .get_identity_flags((identifier, Some(traits), Some(true)))
         .await
         .unwrap()
         .all_flags();
@@ -464,8 +736,71 @@ async fn test_get_identity_flags_calls_api_when_no_local_environment_with_transi
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
+#[tokio::test]
+async fn test_get_identity_flags_calls_api_for_identity_of_another_environment(
+    #[case] client: ClientKind,
+    mock_server: MockServer,
+    environment_json: serde_json::Value,
+    identities_json: serde_json::Value,
+) {
+    // Given
+    let other_environment_key = "ser.other_environment_key";
+    let _environment_mock = mock_server.mock(|when, then| {
+        when.method(GET)
+            .path("/api/v1/environment-document/")
+            .header("X-Environment-Key", ENVIRONMENT_KEY);
+        then.status(200).json_body(environment_json);
+    });
+    let api_mock = mock_server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/v1/identities/")
+            .header("X-Environment-Key", other_environment_key)
+            .json_body(serde_json::json!({
+                "identifier": "test_identity",
+                "traits": [{"trait_key": "foo", "trait_value": "bar", "transient": true}],
+                "transient": false,
+            }));
+        then.status(200).json_body(identities_json);
+    });
+    let flagsmith_options = FlagsmithOptions {
+        api_url: mock_server.url("/api/v1/"),
+        enable_local_evaluation: true,
+        ..Default::default()
+    };
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let context = IdentityContext::new("test_identity")
+        .with_transient_trait(
+            "foo",
+            FlagsmithValue {
+                value: "bar".to_string(),
+                value_type: FlagsmithValueType::String,
+            },
+        )
+        .with_environment_key(other_environment_key);
+
+    // When
+    let flags = flagsmith.get_identity_flags(context.clone()).await.unwrap();
+
+    // Then
+    api_mock.assert();
+    assert_eq!(
+        flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        fixtures::FEATURE_1_STR_VALUE
+    );
+    assert!(flagsmith.get_identity_segments(context).await.is_err());
+}
+
+#[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_default_flag_is_not_used_when_environment_flags_returned(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     flags_json: serde_json::Value,
     default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
@@ -482,7 +817,8 @@ async fn test_default_flag_is_not_used_when_environment_flags_returned(
         default_flag_handler: Some(default_flag_handler),
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let flags = flagsmith.get_environment_flags().await.unwrap();
@@ -500,8 +836,11 @@ async fn test_default_flag_is_not_used_when_environment_flags_returned(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_default_flag_is_used_when_no_matching_environment_flag_returned(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     flags_json: serde_json::Value,
     default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
@@ -518,7 +857,8 @@ async fn test_default_flag_is_used_when_no_matching_environment_flag_returned(
         default_flag_handler: Some(default_flag_handler),
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let flags = flagsmith.get_environment_flags().await.unwrap();
@@ -534,8 +874,11 @@ async fn test_default_flag_is_used_when_no_matching_environment_flag_returned(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_default_flag_is_not_used_when_identity_flags_returned(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     identities_json: serde_json::Value,
     default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
@@ -559,13 +902,11 @@ async fn test_default_flag_is_not_used_when_identity_flags_returned(
         default_flag_handler: Some(default_flag_handler),
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
-    let flags = flagsmith
-        .get_identity_flags(identifier, None, None)
-        .await
-        .unwrap();
+    let flags = flagsmith.get_identity_flags(identifier).await.unwrap();
     let flag = flags.get_flag(fixtures::FEATURE_1_NAME).unwrap();
     // Then
     assert_eq!(flag.feature_name, fixtures::FEATURE_1_NAME);
@@ -580,8 +921,11 @@ async fn test_default_flag_is_not_used_when_identity_flags_returned(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_default_flag_is_used_when_no_matching_identity_flags_returned(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     identities_json: serde_json::Value,
     default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
@@ -605,13 +949,11 @@ async fn test_default_flag_is_used_when_no_matching_identity_flags_returned(
         default_flag_handler: Some(default_flag_handler),
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
-    let flags = flagsmith
-        .get_identity_flags(identifier, None, None)
-        .await
-        .unwrap();
+    let flags = flagsmith.get_identity_flags(identifier).await.unwrap();
     let flag = flags.get_flag("feature_that_does_not_exists").unwrap();
     // Then
     assert_eq!(flag.is_default, true);
@@ -624,8 +966,11 @@ async fn test_default_flag_is_used_when_no_matching_identity_flags_returned(
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_default_flags_are_used_if_api_error_and_default_flag_handler_given_for_environment(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
 ) {
@@ -642,7 +987,8 @@ async fn test_default_flags_are_used_if_api_error_and_default_flag_handler_given
         default_flag_handler: Some(default_flag_handler),
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let flags = flagsmith.get_environment_flags().await.unwrap();
@@ -658,8 +1004,11 @@ async fn test_default_flags_are_used_if_api_error_and_default_flag_handler_given
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_default_flags_are_used_if_api_error_and_default_flag_handler_given_for_identity(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
 ) {
@@ -682,13 +1031,11 @@ async fn test_default_flags_are_used_if_api_error_and_default_flag_handler_given
         default_flag_handler: Some(default_flag_handler),
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
-    let flags = flagsmith
-        .get_identity_flags(identifier, None, None)
-        .await
-        .unwrap();
+    let flags = flagsmith.get_identity_flags(identifier).await.unwrap();
     let flag = flags.get_flag("feature_that_does_not_exists").unwrap();
     // Then
     assert_eq!(flag.is_default, true);
@@ -701,8 +1048,11 @@ async fn test_default_flags_are_used_if_api_error_and_default_flag_handler_given
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_flagsmith_api_error_is_returned_if_something_goes_wrong_with_the_request(
+    #[case] client: ClientKind,
     mock_server: MockServer,
 ) {
     // Give
@@ -717,16 +1067,21 @@ async fn test_flagsmith_api_error_is_returned_if_something_goes_wrong_with_the_r
         api_url: url,
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
 
     // When
     let err = flagsmith.get_environment_flags().await.err().unwrap();
-    assert_eq!(err.kind, flagsmith::error::ErrorKind::FlagsmithAPIError);
+    assert_eq!(err.kind(), flagsmith::error::ErrorKind::FlagsmithAPIError);
+    assert!(matches!(err, Error::Http { status, .. } if status.as_u16() == 502));
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
 async fn test_flagsmith_client_error_is_returned_if_get_flag_is_called_with_a_flag_that_does_not_exists_without_default_handler(
+    #[case] client: ClientKind,
     mock_server: MockServer,
     flags_json: serde_json::Value,
 ) {
@@ -742,7 +1097,8 @@ async fn test_flagsmith_client_error_is_returned_if_get_flag_is_called_with_a_fl
         api_url: url,
         ..Default::default()
     };
-    let flagsmith = Flagsmith::new(ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
     // When
     let err = flagsmith
         .get_environment_flags()
@@ -753,19 +1109,76 @@ async fn test_flagsmith_client_error_is_returned_if_get_flag_is_called_with_a_fl
         .unwrap();
 
     // Then
-    assert_eq!(err.kind, flagsmith::error::ErrorKind::FlagsmithAPIError);
+    assert_eq!(
+        err.kind(),
+        flagsmith::error::ErrorKind::FlagsmithClientError
+    );
+    assert!(matches!(err, Error::FlagNotFound { name } if name == "flag_that_does_not_exists"));
+}
+
+#[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
+#[tokio::test]
+async fn test_dropping_the_client_flushes_pending_analytics(
+    #[case] client: ClientKind,
+    mock_server: MockServer,
+    flags_json: serde_json::Value,
+) {
+    // Given
+    let _flags_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/flags/");
+        then.status(200).json_body(flags_json);
+    });
+    let analytics_mock = mock_server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/v1/analytics/flags/")
+            .json_body(serde_json::json!({ fixtures::FEATURE_1_NAME: 1 }));
+        then.status(200);
+    });
+    let flagsmith_options = FlagsmithOptions {
+        api_url: mock_server.url("/api/v1/"),
+        enable_analytics: true,
+        ..Default::default()
+    };
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    flagsmith
+        .get_environment_flags()
+        .await
+        .unwrap()
+        .is_feature_enabled(fixtures::FEATURE_1_NAME)
+        .unwrap();
+
+    // When
+    drop(flagsmith);
+
+    // Then
+    for _ in 0..50 {
+        if analytics_mock.hits() > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    analytics_mock.assert();
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
-async fn test_get_identity_segments_no_traits(#[future] local_eval_flagsmith: Flagsmith) {
+async fn test_get_identity_segments_no_traits(
+    #[case] client: ClientKind,
+    environment_json: serde_json::Value,
+    mock_server: MockServer,
+) {
     // Given
     let identifier = "some_identifier";
 
     // When
-    let segments = local_eval_flagsmith
+    let segments = local_eval_flagsmith(client, environment_json, mock_server)
         .await
-        .get_identity_segments(identifier, None)
+        .get_identity_segments(identifier)
         .await
         .unwrap();
 
@@ -774,8 +1187,14 @@ async fn test_get_identity_segments_no_traits(#[future] local_eval_flagsmith: Fl
 }
 
 #[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
 #[tokio::test]
-async fn test_get_identity_segments_with_valid_trait(#[future] local_eval_flagsmith: Flagsmith) {
+async fn test_get_identity_segments_with_valid_trait(
+    #[case] client: ClientKind,
+    environment_json: serde_json::Value,
+    mock_server: MockServer,
+) {
     // Given
     let identifier = "some_identifier";
 
@@ -791,9 +1210,9 @@ async fn test_get_identity_segments_with_valid_trait(#[future] local_eval_flagsm
         },
     }];
     // When
-    let segments = local_eval_flagsmith
+    let segments = local_eval_flagsmith(client, environment_json, mock_server)
         .await
-        .get_identity_segments(identifier, Some(traits))
+        .get_identity_segments((identifier, Some(traits)))
         .await
         .unwrap();
 
@@ -801,3 +1220,268 @@ async fn test_get_identity_segments_with_valid_trait(#[future] local_eval_flagsm
     assert_eq!(segments.len(), 1);
     assert_eq!(segments[0].name, "Test Segment");
 }
+
+fn live_flags_json(value: &str) -> serde_json::Value {
+    let mut flags = flags_json();
+    flags[0]["feature_state_value"] = serde_json::json!(value);
+    flags
+}
+
+#[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
+#[tokio::test]
+async fn test_offline_fallback_serves_offline_document_until_api_recovers(
+    #[case] client: ClientKind,
+    mock_server: MockServer,
+    default_flag_handler: Arc<dyn default_handler::DefaultHandler + Send + Sync>,
+) {
+    // Given
+    let handler =
+        offline_handler::LocalFileHandler::new("tests/fixtures/environment.json").unwrap();
+    let flagsmith_options = FlagsmithOptions {
+        api_url: mock_server.url("/api/v1/"),
+        offline_handler: Some(Box::new(handler)),
+        offline_fallback: true,
+        default_flag_handler: Some(default_flag_handler),
+        ..Default::default()
+    };
+    let flagsmith =
+        TestFlagsmith::new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+    let mut api_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/flags/");
+        then.status(200).json_body(live_flags_json("live_value"));
+    });
+
+    // When the API is available
+    let flags = flagsmith.get_environment_flags().await.unwrap();
+
+    // Then live data is served
+    assert_eq!(
+        flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        "live_value"
+    );
+
+    // When the API is unavailable
+    api_mock.delete();
+    api_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/flags/");
+        then.status(503);
+    });
+    let flags = flagsmith.get_environment_flags().await.unwrap();
+    let identity_flags = flagsmith.get_identity_flags("test_identity").await.unwrap();
+
+    // Then the offline document is served, and the default handler for the rest
+    assert_eq!(
+        flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        fixtures::FEATURE_1_STR_VALUE
+    );
+    assert_eq!(
+        identity_flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        fixtures::FEATURE_1_STR_VALUE
+    );
+    assert_eq!(
+        flags
+            .get_feature_value_as_string("unknown_feature")
+            .unwrap(),
+        fixtures::DEFAULT_FLAG_HANDLER_FLAG_VALUE
+    );
+
+    // When the API recovers
+    api_mock.delete();
+    mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/flags/");
+        then.status(200)
+            .json_body(live_flags_json("recovered_value"));
+    });
+    let flags = flagsmith.get_environment_flags().await.unwrap();
+
+    // Then live data is served again
+    assert_eq!(
+        flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        "recovered_value"
+    );
+}
+
+#[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
+#[tokio::test]
+async fn test_offline_fallback_with_local_evaluation_starts_from_offline_document(
+    #[case] client: ClientKind,
+    mock_server: MockServer,
+    environment_json: serde_json::Value,
+) {
+    // Given
+    let mut api_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/environment-document/");
+        then.status(503);
+    });
+    let handler =
+        offline_handler::LocalFileHandler::new("tests/fixtures/environment.json").unwrap();
+    let flagsmith_options = FlagsmithOptions {
+        api_url: mock_server.url("/api/v1/"),
+        enable_local_evaluation: true,
+        offline_handler: Some(Box::new(handler)),
+        offline_fallback: true,
+        ..Default::default()
+    };
+
+    // When the initial fetch fails
+    let mut flagsmith =
+        TestFlagsmith::try_new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options)
+            .await
+            .unwrap();
+    let flags = flagsmith.get_environment_flags().await.unwrap();
+
+    // Then the offline document is served
+    assert_eq!(
+        flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        fixtures::FEATURE_1_STR_VALUE
+    );
+
+    // When the environment is fetched
+    let mut live_environment = environment_json;
+    live_environment["feature_states"][0]["feature_state_value"] = serde_json::json!("live_value");
+    api_mock.delete();
+    api_mock = mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/environment-document/");
+        then.status(200).json_body(live_environment);
+    });
+    flagsmith.update_environment().await.unwrap();
+
+    // Then the live document is served, even once the API fails again
+    api_mock.delete();
+    mock_server.mock(|when, then| {
+        when.method(GET).path("/api/v1/environment-document/");
+        then.status(503);
+    });
+    assert!(flagsmith.update_environment().await.is_err());
+    let flags = flagsmith.get_environment_flags().await.unwrap();
+    assert_eq!(
+        flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        "live_value"
+    );
+}
+
+#[tokio::test]
+async fn test_watched_file_handler_reloads_the_offline_environment() {
+    // Given
+    let path = std::env::temp_dir().join(format!(
+        "flagsmith-offline-environment-{}.json",
+        std::process::id()
+    ));
+    let document = std::fs::read_to_string("tests/fixtures/environment.json").unwrap();
+    std::fs::write(&path, &document).unwrap();
+    let handler =
+        offline_handler::WatchedFileHandler::new(&path, std::time::Duration::from_millis(10))
+            .unwrap();
+    let flagsmith = Flagsmith::new(
+        ENVIRONMENT_KEY.to_string(),
+        FlagsmithOptions {
+            offline_handler: Some(Box::new(handler)),
+            offline_mode: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    let mut events = flagsmith.subscribe();
+    let feature_1_value = || async {
+        flagsmith
+            .get_environment_flags()
+            .await
+            .unwrap()
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap()
+    };
+
+    // When
+    std::fs::write(&path, document.replace("some_value", "reloaded_value")).unwrap();
+    let updated = events.recv().await.unwrap();
+
+    // Then
+    assert!(matches!(updated, EnvironmentEvent::Updated(_)));
+    assert_eq!(feature_1_value().await, "reloaded_value");
+
+    // When
+    std::fs::write(&path, "{not json").unwrap();
+    let failed = events.recv().await.unwrap();
+
+    // Then
+    assert!(matches!(failed, EnvironmentEvent::RefreshFailed { .. }));
+    assert_eq!(feature_1_value().await, "reloaded_value");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
+#[tokio::test]
+async fn test_offline_mode_with_async_offline_handler(
+    #[case] client: ClientKind,
+    #[values(
+        Arc::new(offline_handler::StaticBytesHandler::new(include_bytes!(
+            "fixtures/environment.json"
+        ))),
+        Arc::new(offline_handler::GzipFileHandler::new(
+            "tests/fixtures/environment.json.gz"
+        ))
+    )]
+    handler: Arc<dyn offline_handler::AsyncOfflineHandler>,
+) {
+    // Given
+    let flagsmith_options = FlagsmithOptions {
+        async_offline_handler: Some(handler),
+        offline_mode: true,
+        ..Default::default()
+    };
+
+    // When
+    let flagsmith = TestFlagsmith::try_new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options)
+        .await
+        .unwrap();
+    let identity_flags = flagsmith.get_identity_flags("test_identity").await.unwrap();
+
+    // Then
+    assert_eq!(
+        identity_flags
+            .get_feature_value_as_string(fixtures::FEATURE_1_NAME)
+            .unwrap(),
+        fixtures::FEATURE_1_STR_VALUE
+    );
+}
+
+#[rstest]
+#[case::async_client(ClientKind::Async)]
+#[cfg_attr(feature = "blocking", case::blocking_client(ClientKind::Blocking))]
+#[tokio::test]
+async fn test_async_offline_handler_errors_are_returned_by_try_new(#[case] client: ClientKind) {
+    // Given
+    let reader = tokio::fs::File::open("tests/fixtures/environment.json.gz")
+        .await
+        .unwrap();
+    let flagsmith_options = FlagsmithOptions {
+        async_offline_handler: Some(Arc::new(offline_handler::AsyncReadHandler::new(reader))),
+        offline_mode: true,
+        ..Default::default()
+    };
+
+    // When
+    let result =
+        TestFlagsmith::try_new(client, ENVIRONMENT_KEY.to_string(), flagsmith_options).await;
+
+    // Then
+    assert!(matches!(result, Err(Error::Decode { .. })));
+}